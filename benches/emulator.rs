@@ -0,0 +1,149 @@
+//! Measures the building blocks of emulation: decoding, executing each class of instruction,
+//! drawing sprites and rendering the display.
+
+use c8::{
+    cpu::{Cpu, FONT_START},
+    decode::{decode, Op},
+    display::Display,
+    filters::FILTER_PRESETS,
+    quirks::Quirks,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// The address sprites are drawn from: the font, so that they have pixels set.
+const SPRITE_ADDR: u16 = FONT_START as u16;
+
+/// A representative instruction of each class, except for `FX0A`, which waits for input.
+const OPS: &[(&str, Op)] = &[
+    ("00E0", Op::Clear),
+    ("1NNN", Op::Jump(0x200)),
+    ("3XNN", Op::SkipEq(0x1, 0x42)),
+    ("4XNN", Op::SkipNe(0x1, 0x42)),
+    ("5XY0", Op::SkipEqReg(0x1, 0x2)),
+    ("6XNN", Op::Set(0x1, 0x42)),
+    ("7XNN", Op::AddImm(0x1, 0x01)),
+    ("8XY0", Op::Copy(0x1, 0x2)),
+    ("8XY1", Op::Or(0x1, 0x2)),
+    ("8XY2", Op::And(0x1, 0x2)),
+    ("8XY3", Op::Xor(0x1, 0x2)),
+    ("8XY4", Op::Add(0x1, 0x2)),
+    ("8XY5", Op::Sub(0x1, 0x2)),
+    ("8XY6", Op::ShiftRight(0x1, 0x2)),
+    ("8XY7", Op::SubReverse(0x1, 0x2)),
+    ("8XYE", Op::ShiftLeft(0x1, 0x2)),
+    ("9XY0", Op::SkipNeReg(0x1, 0x2)),
+    ("ANNN", Op::SetIndex(0x300)),
+    ("BNNN", Op::JumpOffset(0x200)),
+    ("CXNN", Op::Random(0x1, 0xFF)),
+    ("EX9E", Op::SkipKey(0x1)),
+    ("EXA1", Op::SkipNotKey(0x1)),
+    ("FX07", Op::GetDelay(0x1)),
+    ("FX15", Op::SetDelay(0x1)),
+    ("FX18", Op::SetSound(0x1)),
+    ("FX1E", Op::AddIndex(0x1)),
+    ("FX29", Op::Font(0x1)),
+    ("FX33", Op::Bcd(0x1)),
+    ("FX55", Op::Store(0xF)),
+    ("FX65", Op::Load(0xF)),
+];
+
+/// A CPU whose loads and stores leave `I` where it is, so that instructions can be repeated.
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::headless(Quirks {
+        modern_ls: true,
+        ..Quirks::default()
+    });
+    cpu.load_program(&[]).expect("empty program fits");
+    exec(&mut cpu, Op::SetIndex(0x300));
+
+    cpu
+}
+
+/// Execute an instruction while setting up a benchmark.
+fn exec(cpu: &mut Cpu, op: Op) {
+    cpu.execute_op(op).expect("instruction doesn't fault");
+}
+
+fn decoding(c: &mut Criterion) {
+    // One instruction of each class.
+    let insts: Vec<u16> = (0..16).map(|class| class << 12 | 0x123).collect();
+
+    c.bench_function("decode", |b| {
+        b.iter(|| {
+            for &inst in &insts {
+                black_box(decode(black_box(inst)));
+            }
+        });
+    });
+}
+
+fn execution(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+
+    for &(class, op) in OPS {
+        let mut cpu = cpu();
+        group.bench_function(class, |b| b.iter(|| cpu.execute_op(black_box(op))));
+    }
+
+    // A call can't be repeated without returning, so both are measured together.
+    let mut cpu = cpu();
+    group.bench_function("2NNN+00EE", |b| {
+        b.iter(|| {
+            cpu.execute_op(black_box(Op::Call(0x300)))
+                .and_then(|()| cpu.execute_op(black_box(Op::Return)))
+        });
+    });
+
+    group.finish();
+}
+
+fn drawing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw");
+
+    for (name, hires, height) in [("8x1", false, 1), ("8x15", false, 15), ("16x16", true, 0)] {
+        let mut cpu = cpu();
+        exec(&mut cpu, Op::SetHires(hires));
+        exec(&mut cpu, Op::SetIndex(SPRITE_ADDR));
+        exec(&mut cpu, Op::Set(0x1, 10));
+        exec(&mut cpu, Op::Set(0x2, 10));
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| cpu.execute_op(black_box(Op::Draw(0x1, 0x2, height))));
+        });
+    }
+
+    group.finish();
+}
+
+fn rendering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    let mut display = Display::headless();
+
+    // A checkerboard, so that every filter has edges to work on.
+    for y in 0..display.height() {
+        for x in (y % 2..display.width()).step_by(2) {
+            display.toggle_pixel(x, y);
+        }
+    }
+
+    // A headless display starts without filters and cycles through the presets.
+    let mut name = "none".to_string();
+
+    for _ in FILTER_PRESETS {
+        group.bench_function(BenchmarkId::from_parameter(&name), |b| {
+            b.iter(|| black_box(display.rasterize()));
+        });
+
+        name = display
+            .cycle_filters()
+            .iter()
+            .map(|filter| filter.name())
+            .collect::<Vec<_>>()
+            .join("+");
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decoding, execution, drawing, rendering);
+criterion_main!(benches);
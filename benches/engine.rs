@@ -0,0 +1,54 @@
+//! Compares the speed of the execution engines, running frames of many instructions.
+
+use c8::{cpu::Cpu, engine::Engine, quirks::Quirks};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// The instructions executed per frame, as needed by demanding XO-CHIP games.
+const IPF: u32 = 1000;
+
+/// A loop of arithmetic, like the inner loops of games.
+const ALU_LOOP: &[u8] = &[
+    0x60, 0x00, // 0x200: LD V0, 0x00
+    0x61, 0x01, // 0x202: LD V1, 0x01
+    0x80, 0x14, // 0x204: ADD V0, V1
+    0x82, 0x03, // 0x206: XOR V2, V0
+    0x83, 0x21, // 0x208: OR V3, V2
+    0x71, 0x01, // 0x20A: ADD V1, 0x01
+    0x31, 0x00, // 0x20C: SE V1, 0x00
+    0x12, 0x04, // 0x20E: JP 0x204
+    0x12, 0x00, // 0x210: JP 0x200
+];
+
+fn engines(c: &mut Criterion) {
+    let programs: [(&str, &[u8]); 2] = [
+        ("alu-loop", ALU_LOOP),
+        ("test-opcode", include_bytes!("../roms/test_opcode.ch8")),
+    ];
+
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements(u64::from(IPF)));
+
+    for (name, prog) in programs {
+        let engines = [
+            Engine::Interpreter,
+            Engine::Cached,
+            #[cfg(target_arch = "x86_64")]
+            Engine::Jit,
+        ];
+
+        for engine in engines {
+            let mut cpu = Cpu::headless(Quirks::default());
+            cpu.set_engine(engine);
+            cpu.load_program(prog).expect("program fits into memory");
+
+            group.bench_function(BenchmarkId::new(engine.to_string(), name), |b| {
+                b.iter(|| cpu.run_frame(IPF).expect("program doesn't fault"));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, engines);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! Runs arbitrary ROMs with arbitrary key presses on the headless core, which has to report
+//! anything going wrong as a `Fault` rather than panicking.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use c8::{cpu::Cpu, engine::Engine, keyboard::DEFAULT_KEYMAP, quirks::Quirks};
+use libfuzzer_sys::fuzz_target;
+
+/// The maximum number of frames run, so that every input finishes quickly.
+const MAX_FRAMES: usize = 64;
+
+/// The maximum number of instructions executed per frame.
+const MAX_IPF: u32 = 64;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    modern_shift: bool,
+    modern_jwo: bool,
+    modern_ls: bool,
+    key_wait_press: bool,
+    engine: u8,
+    ipf: u8,
+
+    /// The keys held during each frame, one bit per key.
+    frames: Vec<u16>,
+    rom: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut cpu = Cpu::headless(Quirks {
+        modern_shift: input.modern_shift,
+        modern_jwo: input.modern_jwo,
+        modern_ls: input.modern_ls,
+        key_wait_press: input.key_wait_press,
+    });
+
+    let engines = [
+        Engine::Interpreter,
+        Engine::Cached,
+        #[cfg(target_arch = "x86_64")]
+        Engine::Jit,
+    ];
+    cpu.set_engine(engines[usize::from(input.engine) % engines.len()]);
+
+    // ROMs too large for memory are refused with a fault as well.
+    if cpu.load_program(&input.rom).is_err() {
+        return;
+    }
+
+    let ipf = u32::from(input.ipf) % MAX_IPF + 1;
+
+    for &keys in input.frames.iter().take(MAX_FRAMES) {
+        for (key, &scancode) in DEFAULT_KEYMAP.iter().enumerate() {
+            if keys >> key & 1 == 1 {
+                cpu.keyboard().press_key(scancode);
+            } else {
+                cpu.keyboard().release_key(scancode);
+            }
+        }
+
+        if cpu.run_frame(ipf).is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Replace the embedded ROM database with the latest programs.json from the community
+# chip-8-database (https://github.com/chip-8/chip-8-database).
+set -eu
+
+cd "$(dirname "$0")/.."
+curl -fsSL -o src/programs.json \
+    https://raw.githubusercontent.com/chip-8/chip-8-database/master/database/programs.json
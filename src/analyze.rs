@@ -0,0 +1,621 @@
+use crate::{
+    cpu::{FONT, FONT_START, MEM_SIZE, PROG_START},
+    disasm::{disassemble, opcode_class},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io::{self, Write},
+};
+
+/// Check whether an instruction conditionally skips the next one.
+fn is_skip(class: &str) -> bool {
+    matches!(class, "3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1")
+}
+
+/// How control passes from a basic block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// Execution continues with the next instruction.
+    Fallthrough(usize),
+
+    /// A `1NNN` jump.
+    Jump(usize),
+
+    /// A skip instruction skipping the next instruction.
+    Skip(usize),
+
+    /// A `2NNN` call of a subroutine, which returns to the next instruction.
+    Call(usize),
+}
+
+/// A sequence of instructions only entered at the first and left at the last.
+#[derive(Clone, Debug)]
+pub struct Block {
+    /// The addresses of the instructions in the block.
+    pub addrs: Vec<usize>,
+
+    /// Where control passes to after the block.
+    pub edges: Vec<Edge>,
+}
+
+impl Block {
+    pub fn start(&self) -> usize {
+        self.addrs[0]
+    }
+}
+
+/// The result of statically tracing the code reachable from the start of a program.
+///
+/// `I` is only tracked within straight-line code along the path an instruction was first reached
+/// by, so data is only found where it is drawn or loaded shortly after `ANNN`.
+pub struct Analysis {
+    /// Memory as initialized when the program is loaded.
+    mem: Vec<u8>,
+
+    /// The length of the program.
+    pub prog_len: usize,
+
+    /// The reachable instructions, by address.
+    pub insts: BTreeMap<usize, u16>,
+
+    /// The basic blocks, by address.
+    pub blocks: BTreeMap<usize, Block>,
+
+    /// The addresses of subroutines called, and of the program entry.
+    pub functions: BTreeSet<usize>,
+
+    /// The calls between functions, as pairs of caller and callee.
+    pub calls: BTreeSet<(usize, usize)>,
+
+    /// The data referenced by `DXYN` or `FX65` after `ANNN`, as address ranges by start.
+    pub data: BTreeMap<usize, usize>,
+
+    /// The addresses of `BNNN` jumps whose targets can't be determined statically.
+    pub computed_jumps: Vec<usize>,
+
+    /// The addresses at which reachable code contains invalid instructions.
+    pub invalid: Vec<usize>,
+}
+
+impl Analysis {
+    /// Analyze a program.
+    pub fn new(prog: &[u8]) -> Self {
+        let mut mem = vec![0; MEM_SIZE];
+        mem[FONT_START..FONT_START + FONT.len()].copy_from_slice(FONT);
+
+        let prog_len = prog.len().min(MEM_SIZE - PROG_START);
+        mem[PROG_START..PROG_START + prog_len].copy_from_slice(&prog[..prog_len]);
+
+        let mut analysis = Self {
+            mem,
+            prog_len,
+            insts: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            functions: BTreeSet::from([PROG_START]),
+            calls: BTreeSet::new(),
+            data: BTreeMap::new(),
+            computed_jumps: Vec::new(),
+            invalid: Vec::new(),
+        };
+
+        let leaders = analysis.trace_code();
+        analysis.build_blocks(&leaders);
+        analysis.build_call_graph();
+
+        analysis
+    }
+
+    /// Get the byte at an address.
+    pub fn byte(&self, addr: usize) -> u8 {
+        self.mem[addr]
+    }
+
+    /// Get the instruction at an address.
+    pub fn word(&self, addr: usize) -> u16 {
+        u16::from(self.mem[addr]) << 8 | u16::from(self.mem[addr + 1])
+    }
+
+    /// Find the reachable instructions, returning the addresses at which basic blocks start.
+    fn trace_code(&mut self) -> BTreeSet<usize> {
+        let mut leaders = BTreeSet::from([PROG_START]);
+        let mut work = vec![(PROG_START, None)];
+
+        while let Some((mut addr, mut idxr)) = work.pop() {
+            while addr + 1 < MEM_SIZE && !self.insts.contains_key(&addr) {
+                let inst = self.word(addr);
+                let class = opcode_class(inst);
+                let nnn = (inst & 0xFFF) as usize;
+                let x = ((inst >> 8) & 0xF) as usize;
+                let n = (inst & 0xF) as usize;
+
+                if class == "????" || class == "0NNN" {
+                    self.invalid.push(addr);
+                    break;
+                }
+
+                self.insts.insert(addr, inst);
+
+                match class {
+                    "ANNN" => idxr = Some(nnn),
+                    "DXYN" | "FX65" => {
+                        let len = match class {
+                            "DXYN" if n == 0 => 32,
+                            "DXYN" => n,
+                            _ => x + 1,
+                        };
+
+                        if let Some(start) = idxr.filter(|_| len > 0) {
+                            let end = self.data.entry(start).or_default();
+                            *end = (*end).max((start + len).min(MEM_SIZE));
+                        }
+
+                        if class == "FX65" {
+                            idxr = None;
+                        }
+                    }
+                    "FX1E" | "FX29" | "FX55" => idxr = None,
+                    _ => {}
+                }
+
+                match class {
+                    "1NNN" => {
+                        leaders.insert(nnn);
+                        work.push((nnn, idxr));
+                        break;
+                    }
+                    "2NNN" => {
+                        self.functions.insert(nnn);
+                        leaders.extend([nnn, addr + 2]);
+                        work.push((nnn, None));
+
+                        // The subroutine may change I.
+                        idxr = None;
+                    }
+                    "00EE" => break,
+                    "BNNN" => {
+                        self.computed_jumps.push(addr);
+                        break;
+                    }
+                    _ if is_skip(class) => {
+                        leaders.extend([addr + 2, addr + 4]);
+                        work.push((addr + 4, idxr));
+                    }
+                    _ => {}
+                }
+
+                addr += 2;
+            }
+        }
+
+        self.computed_jumps.sort_unstable();
+        self.invalid.sort_unstable();
+        self.invalid.dedup();
+
+        leaders
+    }
+
+    /// Split the reachable instructions into basic blocks.
+    fn build_blocks(&mut self, leaders: &BTreeSet<usize>) {
+        for &start in leaders.iter().filter(|addr| self.insts.contains_key(addr)) {
+            let mut block = Block {
+                addrs: Vec::new(),
+                edges: Vec::new(),
+            };
+            let mut addr = start;
+
+            loop {
+                block.addrs.push(addr);
+
+                let inst = self.insts[&addr];
+                let class = opcode_class(inst);
+                let nnn = (inst & 0xFFF) as usize;
+                let next = addr + 2;
+
+                match class {
+                    "1NNN" => block.edges.push(Edge::Jump(nnn)),
+                    "2NNN" => block
+                        .edges
+                        .extend([Edge::Call(nnn), Edge::Fallthrough(next)]),
+                    "00EE" | "BNNN" => {}
+                    _ if is_skip(class) => {
+                        block
+                            .edges
+                            .extend([Edge::Fallthrough(next), Edge::Skip(next + 2)]);
+                    }
+                    _ if leaders.contains(&next) => block.edges.push(Edge::Fallthrough(next)),
+                    _ if self.insts.contains_key(&next) => {
+                        addr = next;
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                break;
+            }
+
+            // Edges to instructions that turned out to be invalid lead nowhere.
+            block.edges.retain(|edge| match *edge {
+                Edge::Fallthrough(target) | Edge::Jump(target) | Edge::Skip(target) => {
+                    self.insts.contains_key(&target)
+                }
+                Edge::Call(_) => true,
+            });
+
+            self.blocks.insert(start, block);
+        }
+    }
+
+    /// Assign blocks to the functions they are reached from, and find the calls between them.
+    fn build_call_graph(&mut self) {
+        let mut owner = BTreeMap::new();
+
+        for &function in &self.functions {
+            let mut queue = VecDeque::from([function]);
+
+            while let Some(start) = queue.pop_front() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+
+                if owner.contains_key(&start) {
+                    continue;
+                }
+
+                owner.insert(start, function);
+
+                for edge in &block.edges {
+                    match *edge {
+                        Edge::Fallthrough(target) | Edge::Jump(target) | Edge::Skip(target) => {
+                            queue.push_back(target);
+                        }
+                        Edge::Call(callee) => {
+                            self.calls.insert((function, callee));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the ranges of the program that were neither reached as code nor found to be data.
+    pub fn unreached(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+        for addr in PROG_START..PROG_START + self.prog_len {
+            let is_code = self
+                .insts
+                .range(addr.saturating_sub(1)..=addr)
+                .next()
+                .is_some();
+            let is_data = self
+                .data
+                .range(..=addr)
+                .next_back()
+                .is_some_and(|(_, &end)| addr < end);
+
+            if is_code || is_data {
+                continue;
+            }
+
+            match ranges.last_mut() {
+                Some((_, end)) if *end == addr => *end = addr + 1,
+                _ => ranges.push((addr, addr + 1)),
+            }
+        }
+
+        ranges
+    }
+
+    /// Print the functions, the call graph, data, problems, and a listing of the basic blocks.
+    pub fn print_report(&self) {
+        println!("ANALYSIS");
+        println!("--------");
+        println!("INSTRUCTIONS: {}", self.insts.len());
+        println!("BASIC BLOCKS: {}", self.blocks.len());
+
+        println!("\nFUNCTIONS:");
+        for &function in &self.functions {
+            let callers: Vec<_> = self
+                .calls
+                .iter()
+                .filter(|&&(_, callee)| callee == function)
+                .map(|&(caller, _)| format!("{caller:#05X}"))
+                .collect();
+
+            if function == PROG_START {
+                println!("    {function:#05X}  entry");
+            } else {
+                println!("    {function:#05X}  called from {}", callers.join(", "));
+            }
+        }
+
+        println!("\nDATA:");
+        for (&start, &end) in &self.data {
+            println!("    {start:#05X}-{:#05X}  {} bytes", end - 1, end - start);
+        }
+
+        if !self.computed_jumps.is_empty() {
+            println!("\nUNRESOLVED COMPUTED JUMPS:");
+            for &addr in &self.computed_jumps {
+                let inst = self.insts[&addr];
+                println!("    {addr:#05X}  {inst:04X}  {}", disassemble(inst));
+            }
+        }
+
+        if !self.invalid.is_empty() {
+            println!("\nINVALID INSTRUCTIONS IN REACHABLE CODE:");
+            for &addr in &self.invalid {
+                println!("    {addr:#05X}  {:04X}", self.word(addr));
+            }
+        }
+
+        let unreached = self.unreached();
+        if !unreached.is_empty() {
+            println!("\nUNREACHED:");
+            for (start, end) in unreached {
+                println!("    {start:#05X}-{:#05X}  {} bytes", end - 1, end - start);
+            }
+        }
+
+        println!("\nBASIC BLOCKS:");
+        for block in self.blocks.values() {
+            println!("\n  block_{:03X}:", block.start());
+
+            for &addr in &block.addrs {
+                let inst = self.insts[&addr];
+                println!("    {addr:#05X}  {inst:04X}  {}", disassemble(inst));
+            }
+
+            let edges: Vec<_> = block
+                .edges
+                .iter()
+                .map(|edge| match *edge {
+                    Edge::Fallthrough(target) => format!("next {target:#05X}"),
+                    Edge::Jump(target) => format!("jump {target:#05X}"),
+                    Edge::Skip(target) => format!("skip {target:#05X}"),
+                    Edge::Call(target) => format!("call {target:#05X}"),
+                })
+                .collect();
+
+            if !edges.is_empty() {
+                println!("    -> {}", edges.join(", "));
+            }
+        }
+    }
+
+    /// Export the control flow graph in the Graphviz DOT format, with a cluster per function.
+    pub fn write_dot(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut clustered = BTreeSet::new();
+
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        for &function in &self.functions {
+            writeln!(out, "    subgraph cluster_{function:03X} {{")?;
+            writeln!(out, "        label=\"sub_{function:03X}\";")?;
+
+            let mut queue = VecDeque::from([function]);
+
+            while let Some(start) = queue.pop_front() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+
+                if !clustered.insert(start) {
+                    continue;
+                }
+
+                let label = block
+                    .addrs
+                    .iter()
+                    .map(|addr| format!("{addr:03X}: {}\\l", disassemble(self.insts[addr])))
+                    .collect::<Vec<_>>()
+                    .concat();
+
+                let style = if block
+                    .addrs
+                    .last()
+                    .is_some_and(|addr| self.computed_jumps.contains(addr))
+                {
+                    ", color=red"
+                } else {
+                    ""
+                };
+                writeln!(out, "        b_{start:03X} [label=\"{label}\"{style}];")?;
+
+                for edge in &block.edges {
+                    if let Edge::Fallthrough(target) | Edge::Jump(target) | Edge::Skip(target) =
+                        *edge
+                    {
+                        queue.push_back(target);
+                    }
+                }
+            }
+
+            writeln!(out, "    }}")?;
+        }
+
+        for block in self.blocks.values() {
+            let start = block.start();
+
+            for edge in &block.edges {
+                let (target, attrs) = match *edge {
+                    Edge::Fallthrough(target) => (target, ""),
+                    Edge::Jump(target) => (target, " [label=\"jump\"]"),
+                    Edge::Skip(target) => (target, " [style=dashed, label=\"skip\"]"),
+                    Edge::Call(target) => (target, " [color=blue, label=\"call\"]"),
+                };
+
+                writeln!(out, "    b_{start:03X} -> b_{target:03X}{attrs};")?;
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Analyze a program of instructions followed by data bytes.
+    fn analyze(insts: &[u16], data: &[u8]) -> Analysis {
+        let mut prog: Vec<u8> = insts.iter().flat_map(|inst| inst.to_be_bytes()).collect();
+        prog.extend_from_slice(data);
+        Analysis::new(&prog)
+    }
+
+    /// Get the addresses and edges of each basic block.
+    fn blocks(analysis: &Analysis) -> Vec<(Vec<usize>, Vec<Edge>)> {
+        analysis
+            .blocks
+            .values()
+            .map(|block| (block.addrs.clone(), block.edges.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn straight_line_code() {
+        let analysis = analyze(&[0x6005, 0x7001, 0x00E0, 0x1206], &[]);
+
+        assert_eq!(analysis.insts.len(), 4);
+        assert_eq!(
+            blocks(&analysis),
+            [
+                (vec![0x200, 0x202, 0x204], vec![Edge::Fallthrough(0x206)]),
+                (vec![0x206], vec![Edge::Jump(0x206)]),
+            ]
+        );
+        assert_eq!(analysis.functions, BTreeSet::from([0x200]));
+        assert!(analysis.unreached().is_empty());
+    }
+
+    #[test]
+    fn loop_with_exit_skip() {
+        // v0 := 0; loop v0 += 1; if v0 != 10 then again; forever
+        let analysis = analyze(&[0x6000, 0x7001, 0x300A, 0x1202, 0x1208], &[]);
+
+        assert_eq!(
+            blocks(&analysis),
+            [
+                (vec![0x200], vec![Edge::Fallthrough(0x202)]),
+                (
+                    vec![0x202, 0x204],
+                    vec![Edge::Fallthrough(0x206), Edge::Skip(0x208)]
+                ),
+                (vec![0x206], vec![Edge::Jump(0x202)]),
+                (vec![0x208], vec![Edge::Jump(0x208)]),
+            ]
+        );
+        assert!(analysis.computed_jumps.is_empty());
+        assert!(analysis.invalid.is_empty());
+    }
+
+    #[test]
+    fn call_and_return() {
+        // The entry calls 0x204, which calls 0x208.
+        let analysis = analyze(&[0x2204, 0x1202, 0x2208, 0x00EE, 0x6001, 0x00EE], &[]);
+
+        assert_eq!(analysis.functions, BTreeSet::from([0x200, 0x204, 0x208]));
+        assert_eq!(
+            analysis.calls,
+            BTreeSet::from([(0x200, 0x204), (0x204, 0x208)])
+        );
+        assert_eq!(
+            blocks(&analysis),
+            [
+                (
+                    vec![0x200],
+                    vec![Edge::Call(0x204), Edge::Fallthrough(0x202)]
+                ),
+                (vec![0x202], vec![Edge::Jump(0x202)]),
+                (
+                    vec![0x204],
+                    vec![Edge::Call(0x208), Edge::Fallthrough(0x206)]
+                ),
+                (vec![0x206], vec![]),
+                (vec![0x208, 0x20A], vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn computed_jump() {
+        // The targets of the jump table after the BNNN can't be followed statically.
+        let analysis = analyze(&[0x6002, 0xB206, 0x1204, 0x1206, 0x1208], &[]);
+
+        assert_eq!(analysis.computed_jumps, [0x202]);
+        assert_eq!(blocks(&analysis), [(vec![0x200, 0x202], vec![])]);
+        assert_eq!(analysis.unreached(), [(0x204, 0x20A)]);
+    }
+
+    #[test]
+    fn sprite_data_after_code() {
+        let analysis = analyze(
+            &[0xA206, 0xD015, 0x1204],
+            &[0xF0, 0x90, 0x90, 0x90, 0xF0, 0xFF],
+        );
+
+        assert_eq!(analysis.insts.len(), 3);
+        assert_eq!(analysis.data, BTreeMap::from([(0x206, 0x20B)]));
+        assert_eq!(analysis.unreached(), [(0x20B, 0x20C)]);
+    }
+
+    #[test]
+    fn invalid_instructions_end_the_path() {
+        let analysis = analyze(&[0x3000, 0xFFFF, 0x1204], &[]);
+
+        assert_eq!(analysis.invalid, [0x202]);
+        assert_eq!(
+            blocks(&analysis),
+            [
+                (vec![0x200], vec![Edge::Skip(0x204)]),
+                (vec![0x204], vec![Edge::Jump(0x204)])
+            ]
+        );
+    }
+
+    #[test]
+    fn dot_export() {
+        let analysis = analyze(&[0x2204, 0x1202, 0x00EE], &[]);
+        let mut out = Vec::new();
+        analysis
+            .write_dot(&mut out)
+            .expect("writing to a vector succeeds");
+
+        assert_eq!(
+            String::from_utf8(out).expect("DOT is UTF-8"),
+            r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    subgraph cluster_200 {
+        label="sub_200";
+        b_200 [label="200: CALL 0x204\l"];
+        b_202 [label="202: JP 0x202\l"];
+    }
+    subgraph cluster_204 {
+        label="sub_204";
+        b_204 [label="204: RET\l"];
+    }
+    b_200 -> b_204 [color=blue, label="call"];
+    b_200 -> b_202;
+    b_202 -> b_202 [label="jump"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn computed_jumps_are_highlighted() {
+        let analysis = analyze(&[0xB300], &[]);
+        let mut out = Vec::new();
+        analysis
+            .write_dot(&mut out)
+            .expect("writing to a vector succeeds");
+
+        let dot = String::from_utf8(out).expect("DOT is UTF-8");
+        assert!(
+            dot.contains(r#"b_200 [label="200: JP V0, 0x300\l", color=red];"#),
+            "{dot}"
+        );
+    }
+}
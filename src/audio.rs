@@ -0,0 +1,68 @@
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    AudioSubsystem,
+};
+
+/// A square wave generator.
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+/// The buzzer sounded while the sound timer is active.
+pub struct Beeper {
+    device: Option<AudioDevice<SquareWave>>,
+}
+
+impl Beeper {
+    /// Open an audio device playing a tone of the given frequency and volume.
+    pub fn new(audio: &AudioSubsystem, frequency: f32, volume: f32) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(44_100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio.open_playback(None, &spec, |spec| SquareWave {
+            phase_inc: (f64::from(frequency) / f64::from(spec.freq)) as f32,
+            phase: 0.0,
+            volume,
+        })?;
+
+        Ok(Self {
+            device: Some(device),
+        })
+    }
+
+    /// A beeper that never makes a sound.
+    pub const fn silent() -> Self {
+        Self { device: None }
+    }
+
+    /// Start or stop the tone.
+    pub fn set_playing(&self, playing: bool) {
+        if let Some(device) = &self.device {
+            if playing {
+                device.resume();
+            } else {
+                device.pause();
+            }
+        }
+    }
+}
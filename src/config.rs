@@ -0,0 +1,312 @@
+use crate::{
+    display::{Persistence, SCALE},
+    filters::Filter,
+    keyboard::DEFAULT_KEYMAP,
+    palette::{Palette, PaletteSetting},
+    quirks::{Platform, QuirkOverrides, Quirks},
+};
+use sdl2::keyboard::Scancode;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// The name of the configuration file inside the configuration directory.
+const CONFIG_FILE: &str = "c8/c8.toml";
+
+/// The default number of instructions executed per frame.
+const DEFAULT_IPF: u32 = 10;
+
+/// Audio settings that can be given in the configuration file.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioOverrides {
+    pub enabled: Option<bool>,
+    pub frequency: Option<f32>,
+    pub volume: Option<f32>,
+}
+
+/// Settings that can be given in the configuration file, either as defaults or for a specific ROM.
+///
+/// Unset settings fall through to the next, less specific, layer.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    pub platform: Option<Platform>,
+    pub scale: Option<u32>,
+    pub ipf: Option<u32>,
+    pub frame_skip: Option<u32>,
+    pub palette: Option<PaletteSetting>,
+    pub persistence: Option<Persistence>,
+    pub filters: Option<Vec<Filter>>,
+    pub hud: Option<bool>,
+    pub quirks: QuirkOverrides,
+    pub keymap: BTreeMap<String, String>,
+    pub audio: AudioOverrides,
+}
+
+impl Profile {
+    /// Replace any settings set in `other`.
+    ///
+    /// Picking a platform drops the quirks overridden so far, which were meant for another one.
+    pub fn overlay(&mut self, other: &Self) {
+        if other.platform.is_some() {
+            self.quirks = QuirkOverrides::default();
+        }
+
+        self.platform = other.platform.or(self.platform);
+        self.scale = other.scale.or(self.scale);
+        self.ipf = other.ipf.or(self.ipf);
+        self.frame_skip = other.frame_skip.or(self.frame_skip);
+        self.palette = other.palette.clone().or_else(|| self.palette.take());
+        self.persistence = other.persistence.or(self.persistence);
+        self.filters = other.filters.clone().or_else(|| self.filters.take());
+        self.hud = other.hud.or(self.hud);
+        self.quirks.overlay(other.quirks);
+        self.keymap
+            .extend(other.keymap.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.audio.enabled = other.audio.enabled.or(self.audio.enabled);
+        self.audio.frequency = other.audio.frequency.or(self.audio.frequency);
+        self.audio.volume = other.audio.volume.or(self.audio.volume);
+    }
+
+    /// Fill in unset settings with their defaults.
+    pub fn resolve(&self) -> Result<Settings, Box<dyn Error>> {
+        let mut keymap = DEFAULT_KEYMAP;
+
+        for (key, name) in &self.keymap {
+            let idx = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&idx| key.len() == 1 && idx < 16)
+                .ok_or_else(|| format!("invalid CHIP-8 key in keymap: {key:?}"))?;
+            let scancode = Scancode::from_name(name)
+                .ok_or_else(|| format!("unknown key name in keymap: {name:?}"))?;
+
+            keymap[idx as usize] = scancode;
+        }
+
+        Ok(Settings {
+            scale: self.scale.unwrap_or(SCALE as u32).max(1),
+            ipf: self.ipf.unwrap_or(DEFAULT_IPF).max(1),
+            frame_skip: self.frame_skip.unwrap_or(0),
+            palette: self
+                .palette
+                .as_ref()
+                .map_or_else(|| Ok(Palette::default()), PaletteSetting::resolve)?,
+            persistence: self.persistence.unwrap_or_default(),
+            filters: self.filters.clone().unwrap_or_default(),
+            hud: self.hud.unwrap_or(false),
+            quirks: self
+                .quirks
+                .apply(self.platform.map_or_else(Quirks::default, Platform::quirks)),
+            keymap,
+            audio_enabled: self.audio.enabled.unwrap_or(true),
+            audio_frequency: self.audio.frequency.unwrap_or(440.0),
+            audio_volume: self.audio.volume.unwrap_or(0.1).clamp(0.0, 1.0),
+        })
+    }
+}
+
+/// The contents of `c8.toml`.
+///
+/// The defaults are the top-level settings, which are split from the `[roms]` table by hand as
+/// unknown keys can't be rejected in a flattened struct.
+#[derive(Debug, Default, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Config {
+    /// Settings applied to every ROM.
+    pub defaults: Profile,
+
+    /// Settings applied to a specific ROM, keyed by the SHA-1 hash of its contents.
+    pub roms: HashMap<String, Profile>,
+}
+
+impl TryFrom<toml::Table> for Config {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let roms = table
+            .remove("roms")
+            .map(toml::Value::try_into)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self {
+            defaults: toml::Value::Table(table).try_into()?,
+            roms,
+        })
+    }
+}
+
+impl Config {
+    /// The location of the configuration file in the user's configuration directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_FILE))
+    }
+
+    /// Load the configuration from the given file, or from the default location if none is given.
+    ///
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("invalid config file {}: {e}", path.display()).into()),
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(format!("unable to read config file {}: {e}", path.display()).into()),
+        }
+    }
+
+    /// Get the settings profile for the ROM with the given hash, given the command line settings.
+    ///
+    /// Settings detected from the ROM database take precedence over the configured defaults,
+    /// settings configured for the ROM itself over both, and the command line over everything.
+    /// Quirks configured for the ROM are kept even when the command line picks another platform.
+    pub fn profile_for(&self, hash: &str, detected: Option<&Profile>, cli: &Profile) -> Profile {
+        let mut profile = self.defaults.clone();
+        let rom_profile = self.roms.get(hash);
+
+        if let Some(detected) = detected {
+            profile.overlay(detected);
+        }
+
+        if let Some(rom_profile) = rom_profile {
+            profile.overlay(rom_profile);
+        }
+
+        profile.overlay(cli);
+
+        if let Some(rom_profile) = rom_profile.filter(|_| cli.platform.is_some()) {
+            profile.quirks.overlay(rom_profile.quirks);
+        }
+
+        profile
+    }
+}
+
+/// Fully resolved emulator settings.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub scale: u32,
+    pub ipf: u32,
+    pub frame_skip: u32,
+    pub palette: Palette,
+    pub persistence: Persistence,
+    pub filters: Vec<Filter>,
+    pub hud: bool,
+    pub quirks: Quirks,
+    pub keymap: [Scancode; 16],
+    pub audio_enabled: bool,
+    pub audio_frequency: f32,
+    pub audio_volume: f32,
+}
+
+/// Compute the lowercase hexadecimal SHA-1 hash of a ROM.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("scale = 4\n[roms.abc]\nipf = 20").is_ok());
+        assert!(toml::from_str::<Config>("scael = 4").is_err());
+        assert!(toml::from_str::<Config>("[roms.abc]\nscael = 4").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        let detected = Profile {
+            platform: Some(Platform::SuperChip),
+            scale: Some(2),
+            ipf: Some(30),
+            quirks: QuirkOverrides {
+                modern_jwo: Some(false),
+                ..QuirkOverrides::default()
+            },
+            ..Profile::default()
+        };
+        let settings = |config: &str, detected: Option<&Profile>, cli: &Profile| {
+            let config: Config = toml::from_str(config).expect("valid config");
+            config
+                .profile_for("abc", detected, cli)
+                .resolve()
+                .expect("valid settings")
+        };
+        let defaults = "platform = \"chip48\"\nipf = 15\nscale = 3\nquirks = { modern-ls = true }";
+
+        // The defaults apply to ROMs that aren't in the database.
+        let resolved = settings(defaults, None, &Profile::default());
+        assert_eq!((resolved.scale, resolved.ipf), (3, 15));
+        assert_eq!(
+            resolved.quirks,
+            Quirks {
+                modern_ls: true,
+                ..Platform::Chip48.quirks()
+            }
+        );
+
+        // The database beats the defaults, along with the quirks of the platform it detects.
+        let resolved = settings(defaults, Some(&detected), &Profile::default());
+        assert_eq!((resolved.scale, resolved.ipf), (2, 30));
+        assert_eq!(
+            resolved.quirks,
+            Quirks {
+                modern_jwo: false,
+                ..Platform::SuperChip.quirks()
+            }
+        );
+
+        // Settings for the ROM beat the database, and the command line beats everything.
+        let config = "ipf = 15\n[roms.abc]\nipf = 20\nscale = 4";
+        let resolved = settings(config, Some(&detected), &Profile::default());
+        assert_eq!((resolved.scale, resolved.ipf), (4, 20));
+
+        let cli = Profile {
+            ipf: Some(5),
+            ..Profile::default()
+        };
+        let resolved = settings(config, Some(&detected), &cli);
+        assert_eq!((resolved.scale, resolved.ipf), (4, 5));
+
+        // A platform picked for the ROM or on the command line drops the quirks below it, but
+        // quirks set for the ROM itself are kept.
+        let resolved = settings(
+            "[roms.abc]\nplatform = \"originalChip8\"",
+            Some(&detected),
+            &Profile::default(),
+        );
+        assert_eq!(resolved.quirks, Platform::OriginalChip8.quirks());
+
+        let cli = Profile {
+            platform: Some(Platform::OriginalChip8),
+            ..Profile::default()
+        };
+        let resolved = settings(
+            "quirks = { modern-shift = true }\n[roms.abc]\nquirks = { modern-ls = true }",
+            Some(&detected),
+            &cli,
+        );
+        assert_eq!(
+            resolved.quirks,
+            Quirks {
+                modern_ls: true,
+                ..Platform::OriginalChip8.quirks()
+            }
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{
+    cpu::{MEM_SIZE, PROG_START},
+    disasm::{disassemble, opcode_class},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Check whether an instruction conditionally skips the next one.
+fn is_skip(inst: u16) -> bool {
+    matches!(
+        opcode_class(inst),
+        "3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1"
+    )
+}
+
+/// A map of addresses to the assembler source lines they were assembled from.
+///
+/// The file has a line `<hex address> <source file>:<line>` per instruction, e.g.
+/// `202 game.8o:14`. Empty lines and lines starting with `#` are ignored.
+pub struct SymbolMap {
+    lines: HashMap<usize, (String, u32)>,
+}
+
+impl SymbolMap {
+    /// Load a symbol map from a file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read symbol map {}: {e}", path.display()))?;
+        let mut lines = HashMap::new();
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("invalid symbol map {} line {}", path.display(), idx + 1);
+            let (addr, location) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (file, source_line) = location.trim().rsplit_once(':').ok_or_else(invalid)?;
+            let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&addr| addr < MEM_SIZE)
+                .ok_or_else(invalid)?;
+            let source_line = source_line.parse().map_err(|_| invalid())?;
+
+            lines.insert(addr, (file.to_string(), source_line));
+        }
+
+        Ok(Self { lines })
+    }
+}
+
+/// The lines of source covered: hit counts and the outcomes of the skips of each line, by file
+/// and line number.
+type SourceCoverage<'a> = BTreeMap<&'a str, BTreeMap<u32, (u64, Vec<(usize, [u64; 2])>)>>;
+
+/// Tracks which instructions of a program were executed, and which way skips went.
+pub struct Coverage {
+    /// The program, to list the instructions that were never executed.
+    prog: Vec<u8>,
+
+    /// The number of executions of the instruction at each address.
+    hits: Vec<u64>,
+
+    /// The number of times the skip instruction at each address skipped and didn't skip.
+    skips: HashMap<usize, [u64; 2]>,
+}
+
+impl Coverage {
+    pub fn new(prog: &[u8]) -> Self {
+        Self {
+            prog: prog.to_vec(),
+            hits: vec![0; MEM_SIZE],
+            skips: HashMap::new(),
+        }
+    }
+
+    /// Start over with a program that was reset or reloaded, dropping what was recorded so far.
+    pub fn reset(&mut self, prog: &[u8]) {
+        *self = Self::new(prog);
+    }
+
+    /// Record the execution of an instruction, given where execution continued after it.
+    pub fn record(&mut self, pc: u16, inst: u16, next_pc: u16) {
+        let addr = pc as usize % MEM_SIZE;
+        self.hits[addr] += 1;
+
+        if is_skip(inst) {
+            let taken = next_pc != pc.wrapping_add(2);
+            self.skips.entry(addr).or_default()[usize::from(!taken)] += 1;
+        }
+    }
+
+    /// The addresses of the listing: every word of the program, following executed instructions
+    /// at odd addresses, and any executed instructions outside it.
+    fn listing_addrs(&self) -> Vec<usize> {
+        let end = (PROG_START + self.prog.len()).min(MEM_SIZE);
+        let mut addrs = Vec::new();
+        let mut addr = PROG_START;
+
+        while addr < end {
+            addrs.push(addr);
+
+            let odd = self.hits[addr] == 0 && self.hits.get(addr + 1).is_some_and(|&hits| hits > 0);
+            addr += if odd { 1 } else { 2 };
+        }
+
+        addrs.extend(
+            (0..MEM_SIZE).filter(|&addr| self.hits[addr] > 0 && !(PROG_START..end).contains(&addr)),
+        );
+        addrs.sort_unstable();
+
+        addrs
+    }
+
+    /// Get the word of the program at an address, or 0 outside of it.
+    fn word_at(&self, addr: usize) -> u16 {
+        let byte = |addr: usize| {
+            addr.checked_sub(PROG_START)
+                .and_then(|idx| self.prog.get(idx))
+                .copied()
+                .map_or(0, u16::from)
+        };
+
+        byte(addr) << 8 | byte(addr + 1)
+    }
+
+    /// Write a disassembly of the program annotated with the number of executions of each
+    /// instruction, `-` for those never executed, and the outcomes of skips.
+    pub fn write_listing(&self, path: &Path, symbols: Option<&SymbolMap>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        for addr in self.listing_addrs() {
+            let inst = self.word_at(addr);
+            let hits = match self.hits[addr] {
+                0 => "-".to_string(),
+                hits => hits.to_string(),
+            };
+
+            write!(
+                out,
+                "{addr:#05X}  {inst:04X}  {:<18}  {hits:>10}",
+                disassemble(inst)
+            )?;
+
+            if let Some([taken, not_taken]) = self.skips.get(&addr) {
+                write!(out, "  skipped {taken}, not skipped {not_taken}")?;
+            }
+
+            if let Some((file, line)) = symbols.and_then(|symbols| symbols.lines.get(&addr)) {
+                write!(out, "  ; {file}:{line}")?;
+            }
+
+            writeln!(out)?;
+        }
+
+        out.flush()
+    }
+
+    /// Write the coverage in the LCOV tracefile format.
+    ///
+    /// Lines refer to the assembler source lines of the symbol map if there is one, and to the
+    /// lines of the listing written by `write_listing` otherwise.
+    pub fn write_lcov(
+        &self,
+        path: &Path,
+        symbols: Option<&SymbolMap>,
+        listing: &Path,
+    ) -> io::Result<()> {
+        let listing = listing.to_string_lossy();
+        let mut files: SourceCoverage<'_> = BTreeMap::new();
+
+        let locations: Vec<(usize, &str, u32)> = symbols.map_or_else(
+            || {
+                (1..)
+                    .zip(self.listing_addrs())
+                    .map(|(line, addr)| (addr, listing.as_ref(), line))
+                    .collect()
+            },
+            |symbols| {
+                symbols
+                    .lines
+                    .iter()
+                    .map(|(&addr, (file, line))| (addr, file.as_str(), *line))
+                    .collect()
+            },
+        );
+
+        for (addr, file, line) in locations {
+            let (hits, skips) = files.entry(file).or_default().entry(line).or_default();
+            *hits += self.hits[addr];
+
+            if let Some(&outcomes) = self.skips.get(&addr) {
+                skips.push((addr, outcomes));
+            }
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "TN:")?;
+
+        for (file, lines) in files {
+            writeln!(out, "SF:{file}")?;
+
+            let (mut branches, mut branches_hit) = (0, 0);
+
+            for (line, (_, skips)) in &lines {
+                for &(addr, outcomes) in skips {
+                    for (branch, count) in outcomes.iter().enumerate() {
+                        writeln!(out, "BRDA:{line},{addr},{branch},{count}")?;
+                        branches += 1;
+                        branches_hit += usize::from(*count > 0);
+                    }
+                }
+            }
+
+            writeln!(out, "BRF:{branches}")?;
+            writeln!(out, "BRH:{branches_hit}")?;
+
+            for (line, (hits, _)) in &lines {
+                writeln!(out, "DA:{line},{hits}")?;
+            }
+
+            let lines_hit = lines.values().filter(|(hits, _)| *hits > 0).count();
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "LH:{lines_hit}")?;
+            writeln!(out, "end_of_record")?;
+        }
+
+        out.flush()
+    }
+
+    /// Print how much of the program was executed.
+    pub fn print_summary(&self) {
+        let words = self.listing_addrs();
+        let executed = words.iter().filter(|&&addr| self.hits[addr] > 0).count();
+        let outcomes = self.skips.len() * 2;
+        let outcomes_hit: usize = self
+            .skips
+            .values()
+            .map(|counts| counts.iter().filter(|&&count| count > 0).count())
+            .sum();
+
+        println!("\nCOVERAGE");
+        println!("--------");
+        println!("INSTRUCTIONS EXECUTED: {executed} of {} words", words.len());
+        println!("SKIP OUTCOMES:         {outcomes_hit} of {outcomes}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    /// A program with a skip that is always taken and an instruction after a loop.
+    const PROGRAM: &[u8] = &[
+        0x60, 0x00, // 0x200: LD V0, 0x00
+        0x30, 0x00, // 0x202: SE V0, 0x00
+        0x00, 0xE0, // 0x204: CLS
+        0x12, 0x06, // 0x206: JP 0x206
+        0x00, 0xE0, // 0x208: CLS
+    ];
+
+    /// A symbol map of the program, putting the skip and the skipped instruction on one line.
+    const SYMBOLS: &str = "\
+        # Assembled from game.8o\n\
+        200 game.8o:3\n\
+        202 game.8o:4\n\
+        0x204 game.8o:4\n\
+        \n\
+        206 game.8o:6\n\
+        208 game.8o:8\n";
+
+    /// The coverage of running the program into its loop.
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::new(PROGRAM);
+        coverage.record(0x200, 0x6000, 0x202);
+        coverage.record(0x202, 0x3000, 0x206);
+        coverage.record(0x206, 0x1206, 0x206);
+        coverage.record(0x206, 0x1206, 0x206);
+        coverage
+    }
+
+    /// A path for a temporary file of a test.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("c8-coverage-{}-{name}", std::process::id()))
+    }
+
+    /// Write a file with the given function and read it back.
+    fn written(name: &str, write: impl FnOnce(&Path) -> io::Result<()>) -> String {
+        let path = temp_path(name);
+        write(&path).expect("writes the file");
+        let contents = fs::read_to_string(&path).expect("reads the file");
+        fs::remove_file(&path).expect("removes the file");
+        contents
+    }
+
+    fn symbols() -> SymbolMap {
+        let path = temp_path("symbols");
+        fs::write(&path, SYMBOLS).expect("writes the symbol map");
+        let symbols = SymbolMap::load(&path);
+        fs::remove_file(&path).expect("removes the symbol map");
+        symbols.expect("symbol map is valid")
+    }
+
+    #[test]
+    fn symbol_map_format() {
+        let symbols = symbols();
+
+        assert_eq!(symbols.lines.len(), 5);
+        assert_eq!(symbols.lines[&0x204], ("game.8o".to_string(), 4));
+
+        for invalid in [
+            "200",
+            "200 game.8o",
+            "200 game.8o:x",
+            "1000 game.8o:1",
+            "zz a:1",
+        ] {
+            let path = temp_path("invalid-symbols");
+            fs::write(&path, format!("# comment\n{invalid}\n")).expect("writes the symbol map");
+            let error = SymbolMap::load(&path).err().expect("symbol map is invalid");
+            fs::remove_file(&path).expect("removes the symbol map");
+
+            assert!(error.to_string().ends_with("line 2"), "{invalid}: {error}");
+        }
+    }
+
+    #[test]
+    fn listing() {
+        let listing = written("listing", |path| {
+            coverage().write_listing(path, Some(&symbols()))
+        });
+
+        assert_eq!(
+            listing,
+            "\
+0x200  6000  LD V0, 0x00                  1  ; game.8o:3
+0x202  3000  SE V0, 0x00                  1  skipped 1, not skipped 0  ; game.8o:4
+0x204  00E0  CLS                          -  ; game.8o:4
+0x206  1206  JP 0x206                     2  ; game.8o:6
+0x208  00E0  CLS                          -  ; game.8o:8
+"
+        );
+    }
+
+    #[test]
+    fn lcov_of_source_lines() {
+        let lcov = written("source.info", |path| {
+            coverage().write_lcov(path, Some(&symbols()), Path::new("listing.txt"))
+        });
+
+        assert_eq!(
+            lcov,
+            "TN:\nSF:game.8o\nBRDA:4,514,0,1\nBRDA:4,514,1,0\nBRF:2\nBRH:1\nDA:3,1\nDA:4,1\n\
+             DA:6,2\nDA:8,0\nLF:4\nLH:3\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn lcov_of_listing_lines() {
+        let lcov = written("listing.info", |path| {
+            coverage().write_lcov(path, None, Path::new("listing.txt"))
+        });
+
+        assert_eq!(
+            lcov,
+            "TN:\nSF:listing.txt\nBRDA:2,514,0,1\nBRDA:2,514,1,0\nBRF:2\nBRH:1\nDA:1,1\n\
+             DA:2,1\nDA:3,0\nDA:4,2\nDA:5,0\nLF:5\nLH:3\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn reset_drops_what_was_recorded() {
+        let mut coverage = Coverage::new(&[0x30, 0x00, 0x00, 0xE0]);
+        coverage.record(0x200, 0x3000, 0x204);
+        coverage.record(0x204, 0x00E0, 0x206);
+
+        coverage.reset(&[0x00, 0xE0]);
+
+        assert_eq!(coverage.prog, [0x00, 0xE0]);
+        assert!(coverage.hits.iter().all(|&hits| hits == 0));
+        assert!(coverage.skips.is_empty());
+        assert_eq!(coverage.listing_addrs(), [0x200]);
+    }
+
+    #[test]
+    fn skips_with_any_low_nibble_are_recorded() {
+        let mut coverage = Coverage::new(&[0x51, 0x21]);
+        coverage.record(0x200, 0x5121, 0x204);
+
+        assert_eq!(coverage.skips[&0x200], [1, 0]);
+    }
+}
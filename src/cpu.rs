@@ -1,13 +1,35 @@
+#[cfg(target_arch = "x86_64")]
+use crate::jit::{Jit, JitState};
 use crate::{
-    display::{Display, HEIGHT, SCALE, WIDTH},
-    keyboard::Keyboard,
+    audio::Beeper,
+    coverage::Coverage,
+    decode::{decode, Op},
+    display::{Display, WIDTH},
+    engine::{BlockCache, Engine},
+    keyboard::{Keyboard, DEFAULT_KEYMAP},
+    profile::Profiler,
+    program::Watcher,
+    quirks::Quirks,
+    sanitize::Sanitizer,
+    speed::Speed,
+    trace::{TraceState, Tracer},
 };
 use arrayvec::ArrayVec;
-use sdl2::{event::Event, keyboard::Scancode, EventPump};
-use std::time::Duration;
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::{Mod, Scancode},
+    EventPump,
+};
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 /// The commonly used font.
-const FONT: &[u8] = &[
+pub const FONT: &[u8] = &[
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -27,13 +49,83 @@ const FONT: &[u8] = &[
 ];
 
 /// The memory address at which the font conventionally starts.
-const FONT_START: usize = 0x050;
+pub const FONT_START: usize = 0x050;
 
 /// The memory address at which programs start.
-const PROG_START: usize = 0x200;
+pub const PROG_START: usize = 0x200;
 
 /// The size of memory in bytes.
-const MEM_SIZE: usize = 0x1000;
+pub const MEM_SIZE: usize = 0x1000;
+
+/// The duration of a frame, at which the timers are decremented and the display is refreshed.
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// How often the performance statistics shown on the heads-up display are recalculated.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An error a program runs into, which stops its execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The program of the given size doesn't fit into memory.
+    ProgramTooLarge(usize),
+
+    /// An instruction that doesn't exist was executed.
+    InvalidInstruction(u16),
+
+    /// A subroutine was called with 16 return addresses on the stack already.
+    StackOverflow,
+
+    /// A subroutine returned without a return address on the stack.
+    StackUnderflow,
+
+    /// Memory was accessed past its end, at the given address.
+    OutOfBounds(usize),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProgramTooLarge(len) => write!(
+                f,
+                "program of {len} bytes doesn't fit into the {} bytes of memory after {PROG_START:#05X}",
+                MEM_SIZE - PROG_START
+            ),
+            Self::InvalidInstruction(inst) => write!(f, "invalid instruction: {inst:#06X}"),
+            Self::StackOverflow => write!(f, "stack overflow: more than 16 nested calls"),
+            Self::StackUnderflow => write!(f, "return without a return address on the stack"),
+            Self::OutOfBounds(addr) => write!(f, "memory access past its end at {addr:#X}"),
+        }
+    }
+}
+
+impl Error for Fault {}
+
+/// Options controlling how a program is run.
+pub struct RunOptions {
+    /// The number of instructions executed per frame.
+    pub ipf: u32,
+
+    /// The maximum number of consecutive frames not presented when falling behind.
+    pub frame_skip: u32,
+
+    /// The initial speed of emulation.
+    pub speed: Speed,
+
+    /// Whether to start paused.
+    pub paused: bool,
+
+    /// Whether to step through instructions individually.
+    pub step: bool,
+
+    /// Whether to run without showing the display.
+    pub no_display: bool,
+
+    /// The program to reload whenever it changes on disk, if any.
+    pub watch: Option<PathBuf>,
+
+    /// The ranges of memory kept when the program is reset or reloaded.
+    pub preserve: Vec<Range<usize>>,
+}
 
 /// CPU state.
 pub struct Cpu {
@@ -64,16 +156,42 @@ pub struct Cpu {
     /// The keyboard.
     keyboard: Keyboard,
 
-    /// The SDL event pump.
-    event_pump: EventPump,
+    /// The buzzer sounded by the sound timer.
+    beeper: Beeper,
+
+    /// The behaviours used for ambiguous instructions.
+    quirks: Quirks,
+
+    /// The key pressed while waiting on the get key instruction, if any.
+    held_key: Option<u8>,
+
+    /// The keys that were already held while waiting on the get key instruction with the
+    /// `key_wait_press` quirk, as a bit for each key, or `None` when not waiting.
+    wait_keys: Option<u16>,
+
+    /// The trace log executed instructions are written to, if any.
+    tracer: Option<Tracer>,
+
+    /// The profiler executed instructions are counted by, if any.
+    profiler: Option<Profiler>,
+
+    /// The coverage executed instructions are recorded in, if any.
+    coverage: Option<Coverage>,
+
+    /// The sanitizer executed instructions are checked by, if any.
+    sanitizer: Option<Sanitizer>,
 
-    /// A flag controlling whether the display should be rerendered after an instruction.
-    rerender: bool,
+    /// The decoded basic blocks, when executing them with the cached engine.
+    block_cache: Option<BlockCache>,
+
+    /// The compiled basic blocks, when executing them with the JIT engine.
+    #[cfg(target_arch = "x86_64")]
+    jit: Option<Jit>,
 }
 
 impl Cpu {
     /// Create a new CPU initialized with default values.
-    pub fn new(display: Display, event_pump: EventPump) -> Self {
+    pub fn new(display: Display, keyboard: Keyboard, beeper: Beeper, quirks: Quirks) -> Self {
         Self {
             mem: [0; MEM_SIZE],
             pc: 0,
@@ -83,28 +201,77 @@ impl Cpu {
             dtr: 0,
             str: 0,
             display,
-            event_pump,
-            keyboard: Keyboard::new(),
-            rerender: false,
+            keyboard,
+            beeper,
+            quirks,
+            held_key: None,
+            wait_keys: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            sanitizer: None,
+            block_cache: None,
+            #[cfg(target_arch = "x86_64")]
+            jit: None,
+        }
+    }
+
+    /// Create a CPU without a window, keyboard input or sound, e.g. for tests and benchmarks.
+    pub fn headless(quirks: Quirks) -> Self {
+        Self::new(
+            Display::headless(),
+            Keyboard::new(DEFAULT_KEYMAP),
+            Beeper::silent(),
+            quirks,
+        )
+    }
+
+    /// Select how instructions are executed.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.block_cache = (engine == Engine::Cached).then(BlockCache::new);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.jit = (engine == Engine::Jit).then(|| Jit::new(self.quirks));
         }
     }
 
+    /// Write a trace log of executed instructions.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
     /// Read a byte from memory.
-    fn read_byte(&self, addr: usize) -> u8 {
-        self.mem[addr]
+    fn read_byte(&self, addr: usize) -> Result<u8, Fault> {
+        self.mem.get(addr).copied().ok_or(Fault::OutOfBounds(addr))
     }
 
     /// Write a byte to memory.
-    fn write_byte(&mut self, addr: usize, byte: u8) {
-        self.mem[addr] = byte;
+    fn write_byte(&mut self, addr: usize, byte: u8) -> Result<(), Fault> {
+        *self.mem.get_mut(addr).ok_or(Fault::OutOfBounds(addr))? = byte;
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.memory_written();
+        }
+
+        if let Some(block_cache) = &mut self.block_cache {
+            block_cache.write(addr);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(jit) = &mut self.jit {
+            jit.write(addr);
+        }
+
+        Ok(())
     }
 
     /// Read a word from memory.
-    fn read_word(&self, addr: usize) -> u16 {
-        let hi = self.read_byte(addr) as u16;
-        let lo = self.read_byte(addr + 1) as u16;
+    fn read_word(&self, addr: usize) -> Result<u16, Fault> {
+        let hi = u16::from(self.read_byte(addr)?);
+        let lo = u16::from(self.read_byte(addr + 1)?);
 
-        hi << 8 | lo
+        Ok(hi << 8 | lo)
     }
 
     /// Get the value of a register.
@@ -124,13 +291,16 @@ impl Cpu {
     }
 
     /// Push to the stack.
-    fn push_stack(&mut self) {
-        self.stack.push(self.pc);
+    fn push_stack(&mut self) -> Result<(), Fault> {
+        self.stack
+            .try_push(self.pc)
+            .map_err(|_| Fault::StackOverflow)
     }
 
     /// Pop from the stack.
-    fn pop_stack(&mut self) {
-        self.pc = self.stack.pop().expect("no return address");
+    fn pop_stack(&mut self) -> Result<(), Fault> {
+        self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?;
+        Ok(())
     }
 
     /// Skip the next instruction if the given condition is true.
@@ -143,7 +313,7 @@ impl Cpu {
         let regx_val = self.get_reg(regx);
         let regy_val = self.get_reg(regy);
 
-        if cfg!(feature = "modern-shift") {
+        if self.quirks.modern_shift {
             let lsb = regx_val & 1;
             self.set_reg(regx, regx_val >> 1);
             self.set_reg(0xF, lsb);
@@ -160,7 +330,7 @@ impl Cpu {
         let regx_val = self.get_reg(regx);
         let regy_val = self.get_reg(regy);
 
-        if cfg!(feature = "modern-shift") {
+        if self.quirks.modern_shift {
             let msb = regx_val >> 7;
             self.set_reg(regx, regx_val << 1);
             self.set_reg(0xF, msb);
@@ -172,15 +342,105 @@ impl Cpu {
         }
     }
 
+    /// Implementation of the draw instruction.
+    ///
+    /// Sprites are drawn from memory at I, starting at (VX, VY) wrapped to the display and clipped
+    /// at its edges. In the SUPER-CHIP high resolution mode, a height of 0 draws a 16x16 sprite.
+    fn inst_draw(&mut self, regx: usize, regy: usize, height: usize) -> Result<(), Fault> {
+        let (width, height, row_bytes) = if height == 0 && self.display.width() > WIDTH {
+            (16, 16, 2)
+        } else {
+            (8, height, 1)
+        };
+
+        let xcoord = self.get_reg(regx) as usize % self.display.width();
+        let ycoord = self.get_reg(regy) as usize % self.display.height();
+
+        self.set_reg(0xF, 0);
+
+        for row in 0..height {
+            let ypos = ycoord + row;
+
+            if ypos >= self.display.height() {
+                break;
+            }
+
+            let addr = self.idxr as usize + row * row_bytes;
+            let sprite_row = (0..row_bytes).try_fold(0u16, |bits, byte| {
+                Ok::<_, Fault>(bits << 8 | u16::from(self.read_byte(addr + byte)?))
+            })?;
+
+            for col in 0..width {
+                let xpos = xcoord + col;
+
+                if xpos >= self.display.width() {
+                    break;
+                }
+
+                if (sprite_row >> (width - 1 - col)) & 1 == 1 {
+                    if self.display.get_pixel(xpos, ypos) {
+                        self.set_reg(0xF, 1);
+                    }
+
+                    self.display.toggle_pixel(xpos, ypos);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Implementation of the get key instruction.
+    ///
+    /// Rather than blocking, the program counter is held on this instruction until a key has been
+    /// pressed and released again, as on the COSMAC VIP, so that timers and the display keep running
+    /// in the meantime. With the `key_wait_press` quirk, the instruction completes on the press of a
+    /// key that wasn't already held when the wait started, so that a key held since the previous
+    /// get key instruction isn't taken as another press.
+    fn inst_get_key(&mut self, regx: usize) {
+        if self.quirks.key_wait_press {
+            let pressed = self.keyboard.pressed_keys();
+            let held = self.wait_keys.unwrap_or(pressed);
+            let new = pressed & !held;
+
+            if new != 0 {
+                self.wait_keys = None;
+                self.set_reg(regx, new.trailing_zeros() as u8);
+                return;
+            }
+
+            // A key released while waiting counts when it is pressed again.
+            self.wait_keys = Some(held & pressed);
+            self.pc -= 2;
+            return;
+        }
+
+        match self.held_key {
+            None => self.held_key = self.keyboard.pressed_key(),
+            Some(key) if !self.keyboard.is_key_pressed(key as usize) => {
+                self.held_key = None;
+                self.set_reg(regx, key);
+                return;
+            }
+            Some(_) => {}
+        }
+
+        self.pc -= 2;
+    }
+
     /// Fetch the next instruction.
-    fn next_inst(&mut self) -> u16 {
-        let inst = self.read_word(self.pc as usize);
+    fn next_inst(&mut self) -> Result<u16, Fault> {
+        let inst = self.read_word(self.pc as usize)?;
         self.pc += 2;
-        inst
+        Ok(inst)
     }
 
     /// Load a program into memory and prepare for execution.
-    fn load_program(&mut self, prog: &[u8]) {
+    pub fn load_program(&mut self, prog: &[u8]) -> Result<(), Fault> {
+        if prog.len() > MEM_SIZE - PROG_START {
+            return Err(Fault::ProgramTooLarge(prog.len()));
+        }
+
         // Load the font into memory.
         for (idx, &byte) in FONT.iter().enumerate() {
             self.mem[FONT_START + idx] = byte;
@@ -192,296 +452,675 @@ impl Cpu {
         }
 
         self.pc = PROG_START as u16;
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.memory_written();
+        }
+
+        if let Some(block_cache) = &mut self.block_cache {
+            block_cache.clear();
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(jit) = &mut self.jit {
+            jit.clear();
+        }
+
+        Ok(())
     }
 
-    /// Decode and execute an instruction.
-    fn execute_instruction(&mut self, inst: u16) {
-        self.rerender = false;
+    /// Get the state before executing the instruction at the given address.
+    fn trace_state(&self, pc: u16, inst: u16) -> TraceState<'_> {
+        TraceState {
+            pc,
+            inst,
+            gpvr: &self.gpvr,
+            idxr: self.idxr,
+            dtr: self.dtr,
+            str: self.str,
+            stack: &self.stack,
+            mem: &self.mem,
+            mem_hash: None,
+        }
+    }
 
-        println!("inst: {inst:#X}");
-        println!("PC: {:#X}", self.pc);
+    /// Count executed instructions with a profiler.
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
 
-        // The highest nibble encodes the kind of instruction to be executed.
-        let highest_nibble = inst >> 12;
+    /// Get the profiler executed instructions were counted by, if any.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
 
-        // The remaining nibbles or combination of remaining nibbles can encode certain information such as
-        // registers, immediate numbers, or memory addresses. Extract them all out here and the proper one
-        // will be used inside the instruction.
-        let x = ((inst >> 8) & 0xF) as usize;
-        let y = ((inst >> 4) & 0xF) as usize;
-        let n = (inst & 0xF) as usize;
-        let nn = (inst & 0xFF) as u8;
-        let nnn = (inst & 0xFFF) as usize;
+    /// Record the coverage of executed instructions.
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
 
-        match highest_nibble {
-            0x0 => match nnn {
-                0x0E0 => {
-                    self.display.clear();
-                    self.rerender = true;
-                }
+    /// Get the coverage of executed instructions, if recorded.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
 
-                0x0EE => {
-                    self.pop_stack();
-                }
+    /// Check executed instructions for likely bugs.
+    pub fn set_sanitizer(&mut self, sanitizer: Sanitizer) {
+        self.sanitizer = Some(sanitizer);
+    }
 
-                _ => panic!("invalid instruction: {inst:#X}"),
-            },
-            0x1 => {
-                self.pc = nnn as u16;
-            }
-            0x2 => {
-                self.push_stack();
-                self.pc = nnn as u16;
-            }
-            0x3 => self.skip_inst_if(self.get_reg(x) == nn),
-            0x4 => self.skip_inst_if(self.get_reg(x) != nn),
-            0x5 => self.skip_inst_if(self.get_reg(x) == self.get_reg(y)),
-            0x6 => self.set_reg(x, nn),
-            0x7 => self.set_reg_with_func(x, |val| val.wrapping_add(nn)),
-            0x8 => {
-                let regx = x;
-                let regy = y;
-
-                match n {
-                    0x0 => self.set_reg(regx, self.get_reg(regy)),
-                    0x1 => {
-                        let regy_val = self.get_reg(regy);
-                        self.set_reg_with_func(regx, |regx_val| regx_val | regy_val);
-                    }
-                    0x2 => {
-                        let regy_val = self.get_reg(regy);
-                        self.set_reg_with_func(regx, |regx_val| regx_val & regy_val);
-                    }
-                    0x3 => {
-                        let regy_val = self.get_reg(regy);
-                        self.set_reg_with_func(regx, |regx_val| regx_val ^ regy_val);
-                    }
-                    0x4 => {
-                        let res = self.get_reg(regx) as u16 + self.get_reg(regy) as u16;
+    /// Get the sanitizer executed instructions were checked by, if any.
+    pub fn sanitizer(&self) -> Option<&Sanitizer> {
+        self.sanitizer.as_ref()
+    }
 
-                        if res > 0xFF {
-                            self.set_reg(0xF, 1);
-                        }
+    /// Get the display the program draws on.
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
 
-                        self.set_reg(regx, (res & 0xFF) as u8);
-                    }
-                    0x5 => {
-                        let regx_val = self.get_reg(regx);
-                        let regy_val = self.get_reg(regy);
+    /// Get the keyboard the program reads, e.g. to press keys when running headless.
+    pub fn keyboard(&mut self) -> &mut Keyboard {
+        &mut self.keyboard
+    }
 
-                        self.set_reg_with_func(0xF, |_| (regx_val > regy_val) as u8);
+    /// Fetch and execute the next instruction, writing it to the trace log, counting it,
+    /// recording its coverage and checking it if enabled.
+    fn execute_next(&mut self) -> Result<(), Fault> {
+        let pc = self.pc;
+        let inst = self.next_inst()?;
 
-                        self.set_reg(regx, regx_val.saturating_sub(regy_val));
-                    }
-                    0x6 => self.inst_shiftr(regx, regy),
-                    0x7 => {
-                        let regx_val = self.get_reg(regx);
-                        let regy_val = self.get_reg(regy);
+        if let Some(mut tracer) = self.tracer.take() {
+            match tracer.trace(&self.trace_state(pc, inst)) {
+                Ok(()) => self.tracer = Some(tracer),
+                Err(e) => eprintln!("unable to write trace log, tracing stopped: {e}"),
+            }
+        }
 
-                        self.set_reg_with_func(0xF, |_| (regx_val <= regy_val) as u8);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, inst);
+        }
 
-                        self.set_reg(regx, regy_val.saturating_sub(regx_val));
-                    }
-                    0xE => self.inst_shiftl(regx, regy),
-                    _ => panic!("invalid instruction: {inst:#X}"),
-                }
+        if let Some(mut sanitizer) = self.sanitizer.take() {
+            sanitizer.check(&self.trace_state(pc, inst), self.display.width() > WIDTH);
+            self.sanitizer = Some(sanitizer);
+        }
+
+        self.execute_op(decode(inst))?;
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, inst, self.pc);
+        }
+
+        Ok(())
+    }
+
+    /// Execute a number of instructions.
+    ///
+    /// With the cached or JIT engine, the basic blocks at the program counter are executed from
+    /// the cache or as compiled, unless instructions are instrumented.
+    fn execute_instructions(&mut self, count: u32) -> Result<(), Fault> {
+        let instrumented = self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+            || self.sanitizer.is_some();
+
+        #[cfg(target_arch = "x86_64")]
+        if self.jit.is_some() && !instrumented {
+            return self.execute_compiled(count);
+        }
+
+        // Instrumentation needs every instruction as it was fetched, so it is left to the
+        // interpreter.
+        if self.block_cache.is_none() || instrumented {
+            for _ in 0..count {
+                self.execute_next()?;
             }
-            0x9 => self.skip_inst_if(self.get_reg(x) != self.get_reg(y)),
-            0xA => self.idxr = nnn as u16,
-            0xB => {
-                if cfg!(feature = "modern-jwo") {
-                    self.pc = nnn as u16 + self.get_reg(x) as u16;
-                } else {
-                    self.pc = nnn as u16 + self.get_reg(0) as u16;
+
+            return Ok(());
+        }
+
+        let mut remaining = count as usize;
+
+        while remaining > 0 {
+            let start = self.pc;
+            let block_cache = self.block_cache.as_mut().expect("engine is cached");
+            let block = block_cache.block(&self.mem, start as usize);
+
+            if block.ops.is_empty() {
+                self.execute_next()?;
+                remaining -= 1;
+                continue;
+            }
+
+            // Only the last instruction of a block may continue elsewhere or write to memory, so
+            // the others are executed without checking. A block looping back to itself is run
+            // again without looking it up.
+            loop {
+                let len = block.ops.len().min(remaining);
+
+                for &op in &block.ops[..len] {
+                    self.pc += 2;
+                    self.execute_op(op)?;
+                }
+
+                remaining -= len;
+
+                if remaining == 0 || self.pc != start {
+                    break;
                 }
             }
-            0xC => {
-                let rand = rand::random::<u8>();
-                self.set_reg(x, rand & nn);
+        }
+
+        Ok(())
+    }
+
+    /// Execute a number of instructions with the JIT engine, interpreting those that aren't
+    /// compiled.
+    #[cfg(target_arch = "x86_64")]
+    fn execute_compiled(&mut self, count: u32) -> Result<(), Fault> {
+        let mut remaining = count as usize;
+
+        while remaining > 0 {
+            let start = self.pc;
+            let jit = self.jit.as_mut().expect("engine is JIT");
+
+            // Blocks that would run past the end of the frame are interpreted instead.
+            let Some(block) = jit
+                .block(&self.mem, start as usize)
+                .filter(|block| block.len <= remaining)
+            else {
+                self.execute_next()?;
+                remaining -= 1;
+                continue;
+            };
+
+            let mut state = JitState {
+                gpvr: self.gpvr,
+                idxr: self.idxr,
+                dtr: self.dtr,
+                str: self.str,
+            };
+
+            // A block looping back to itself is run again without looking it up.
+            loop {
+                self.pc = block.run(&mut state);
+                remaining -= block.len;
+
+                if remaining < block.len || self.pc != start {
+                    break;
+                }
             }
-            0xD => {
-                let sprite_height = n;
-                let xcoord = self.get_reg(x) as usize % HEIGHT * SCALE;
-                let ycoord = self.get_reg(y) as usize % WIDTH * SCALE;
 
-                self.set_reg(0xF, 0);
+            (self.gpvr, self.idxr, self.dtr, self.str) =
+                (state.gpvr, state.idxr, state.dtr, state.str);
+        }
 
-                for row in 0..sprite_height {
-                    let sprite_byte = self.read_byte(self.idxr as usize + row);
+        Ok(())
+    }
 
-                    for bit in (0..u8::BITS).rev() {
-                        let sprite_pixel = (sprite_byte >> bit) & 0x1;
+    /// Execute a frame of `ipf` instructions and decrement the timers, without handling events or
+    /// rendering the display.
+    pub fn run_frame(&mut self, ipf: u32) -> Result<(), Fault> {
+        self.execute_instructions(ipf)?;
+        self.tick_timers();
+        Ok(())
+    }
 
-                        if sprite_pixel == 1 {
-                            if self.display.get_pixel(xcoord, ycoord) {
-                                self.set_reg(0xF, 1);
-                            }
-                        }
+    /// Reset the CPU and the display and load the program again, keeping the given ranges of
+    /// memory.
+    ///
+    /// A program that doesn't fit is rejected before anything is reset, so that the running one
+    /// can go on.
+    fn reset(&mut self, prog: &[u8], preserve: &[Range<usize>]) -> Result<(), Fault> {
+        if prog.len() > MEM_SIZE - PROG_START {
+            return Err(Fault::ProgramTooLarge(prog.len()));
+        }
 
-                        self.display.toggle_pixel(xcoord, ycoord);
-                    }
-                }
+        let mem = self.mem;
+
+        self.mem = [0; MEM_SIZE];
+        self.idxr = 0;
+        self.gpvr = [0; 16];
+        self.stack.clear();
+        self.dtr = 0;
+        self.str = 0;
+        self.held_key = None;
+        self.wait_keys = None;
+        self.beeper.set_playing(false);
+        self.display.set_hires(false);
+        self.load_program(prog)?;
+
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.reset(prog.len());
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.reset(prog);
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.reset();
+        }
+
+        for range in preserve {
+            self.mem[range.clone()].copy_from_slice(&mem[range.clone()]);
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.memory_written();
+        }
+
+        Ok(())
+    }
+
+    /// Execute a decoded instruction.
+    pub fn execute_op(&mut self, op: Op) -> Result<(), Fault> {
+        match op {
+            Op::Clear => self.display.clear(),
+            Op::Return => self.pop_stack()?,
+            Op::SetHires(hires) => self.display.set_hires(hires),
+            Op::Jump(nnn) => self.pc = nnn,
+            Op::Call(nnn) => {
+                self.push_stack()?;
+                self.pc = nnn;
+            }
+            Op::SkipEq(x, nn) => self.skip_inst_if(self.get_reg(x) == nn),
+            Op::SkipNe(x, nn) => self.skip_inst_if(self.get_reg(x) != nn),
+            Op::SkipEqReg(x, y) => self.skip_inst_if(self.get_reg(x) == self.get_reg(y)),
+            Op::Set(x, nn) => self.set_reg(x, nn),
+            Op::AddImm(x, nn) => self.set_reg_with_func(x, |val| val.wrapping_add(nn)),
+            Op::Copy(x, y) => self.set_reg(x, self.get_reg(y)),
+            Op::Or(x, y) => {
+                let regy_val = self.get_reg(y);
+                self.set_reg_with_func(x, |regx_val| regx_val | regy_val);
+            }
+            Op::And(x, y) => {
+                let regy_val = self.get_reg(y);
+                self.set_reg_with_func(x, |regx_val| regx_val & regy_val);
+            }
+            Op::Xor(x, y) => {
+                let regy_val = self.get_reg(y);
+                self.set_reg_with_func(x, |regx_val| regx_val ^ regy_val);
+            }
+            // The flag is set after the result, so it wins when VF is the destination.
+            Op::Add(x, y) => {
+                let (res, carry) = self.get_reg(x).overflowing_add(self.get_reg(y));
+
+                self.set_reg(x, res);
+                self.set_reg(0xF, u8::from(carry));
+            }
+            Op::Sub(x, y) => {
+                let (res, borrow) = self.get_reg(x).overflowing_sub(self.get_reg(y));
+
+                self.set_reg(x, res);
+                self.set_reg(0xF, u8::from(!borrow));
+            }
+            Op::ShiftRight(x, y) => self.inst_shiftr(x, y),
+            Op::SubReverse(x, y) => {
+                let (res, borrow) = self.get_reg(y).overflowing_sub(self.get_reg(x));
 
-                self.rerender = true;
+                self.set_reg(x, res);
+                self.set_reg(0xF, u8::from(!borrow));
             }
-            0xE => match (y, n) {
-                (0x9, 0xE) => {
-                    self.skip_inst_if(self.keyboard.is_key_pressed(self.get_reg(x) as usize))
+            Op::ShiftLeft(x, y) => self.inst_shiftl(x, y),
+            Op::SkipNeReg(x, y) => self.skip_inst_if(self.get_reg(x) != self.get_reg(y)),
+            Op::SetIndex(nnn) => self.idxr = nnn,
+            Op::JumpOffset(nnn) => {
+                if self.quirks.modern_jwo {
+                    self.pc = nnn + u16::from(self.get_reg((nnn >> 8) as usize));
+                } else {
+                    self.pc = nnn + u16::from(self.get_reg(0));
                 }
-                (0xA, 0x1) => {
-                    self.skip_inst_if(!self.keyboard.is_key_pressed(self.get_reg(x) as usize))
+            }
+            Op::Random(x, nn) => {
+                let rand = rand::random::<u8>();
+                self.set_reg(x, rand & nn);
+            }
+            Op::Draw(x, y, n) => self.inst_draw(x, y, n)?,
+            // Only the low nibble of VX selects a key, as there are 16 of them.
+            Op::SkipKey(x) => {
+                let key = (self.get_reg(x) & 0xF) as usize;
+                self.skip_inst_if(self.keyboard.is_key_pressed(key));
+            }
+            Op::SkipNotKey(x) => {
+                let key = (self.get_reg(x) & 0xF) as usize;
+                self.skip_inst_if(!self.keyboard.is_key_pressed(key));
+            }
+            Op::GetDelay(x) => self.set_reg(x, self.dtr),
+            Op::GetKey(x) => self.inst_get_key(x),
+            Op::SetDelay(x) => self.dtr = self.get_reg(x),
+            Op::SetSound(x) => self.str = self.get_reg(x),
+            Op::AddIndex(x) => {
+                self.idxr = self.idxr.wrapping_add(u16::from(self.get_reg(x)));
+
+                if self.idxr >= MEM_SIZE as u16 {
+                    self.set_reg(0xF, 1);
                 }
-                _ => panic!("invalid instruction: {inst:#X}"),
-            },
-            0xF => match (y, n) {
-                (0x0, 0x7) => self.set_reg(x, self.dtr),
-                (0x0, 0xA) => loop {
-                    let event = self.event_pump.wait_event();
-
-                    if let Event::KeyDown {
-                        scancode: Some(scancode),
-                        ..
-                    } = event
-                    {
-                        self.keyboard.press_key(scancode);
-
-                        let scancode_hex = match scancode {
-                            Scancode::Num1 => 0,
-                            Scancode::Num2 => 1,
-                            Scancode::Num3 => 2,
-                            Scancode::Num4 => 3,
-                            Scancode::Q => 4,
-                            Scancode::W => 5,
-                            Scancode::E => 6,
-                            Scancode::R => 7,
-                            Scancode::A => 8,
-                            Scancode::S => 9,
-                            Scancode::D => 10,
-                            Scancode::F => 11,
-                            Scancode::Z => 12,
-                            Scancode::X => 13,
-                            Scancode::C => 14,
-                            Scancode::V => 15,
-                            _ => continue,
-                        };
+            }
+            Op::Font(x) => {
+                self.idxr = FONT_START as u16 + u16::from(self.get_reg(x) & 0xF) * 5;
+            }
+            Op::Bcd(x) => {
+                let num = self.get_reg(x);
+                let addr = self.idxr as usize;
 
-                        self.set_reg(x, scancode_hex);
+                self.write_byte(addr, num / 100)?;
+                self.write_byte(addr + 1, (num % 100) / 10)?;
+                self.write_byte(addr + 2, (num % 100) % 10)?;
+            }
+            Op::Store(x) => {
+                if self.quirks.modern_ls {
+                    let addr = self.idxr as usize;
 
-                        break;
+                    for reg in 0..=x {
+                        self.write_byte(addr + reg, self.get_reg(reg))?;
                     }
-                },
-                (0x1, 0x5) => self.dtr = self.get_reg(x),
-                (0x1, 0x8) => self.str = self.get_reg(x),
-                (0x1, 0xE) => {
-                    let regx_val = self.get_reg(x);
-                    let res = self.idxr + regx_val as u16;
-
-                    if res >= MEM_SIZE as u16 {
-                        self.set_reg(0xF, 1);
+                } else {
+                    for reg in 0..=x {
+                        self.write_byte(self.idxr as usize, self.get_reg(reg))?;
+                        self.idxr += 1;
                     }
                 }
-                (0x2, 0x9) => self.idxr = self.get_reg(x) as u16 * 5,
-                (0x3, 0x3) => {
-                    let num = self.get_reg(x);
+            }
+            Op::Load(x) => {
+                if self.quirks.modern_ls {
                     let addr = self.idxr as usize;
 
-                    self.write_byte(addr, num / 100);
-                    self.write_byte(addr + 1, (num % 100) / 10);
-                    self.write_byte(addr + 2, (num % 100) % 10);
-                }
-                (0x5, 0x5) => {
-                    if cfg!(feature = "modern-ls") {
-                        let mut addr = self.idxr as usize;
-
-                        for reg in 0..=x {
-                            self.write_byte(addr, self.get_reg(reg));
-                            addr += 1;
-                        }
-                    } else {
-                        for reg in 0..=x {
-                            self.write_byte(self.idxr as usize, self.get_reg(reg));
-                            self.idxr += 1;
-                        }
+                    for reg in 0..=x {
+                        let val = self.read_byte(addr + reg)?;
+                        self.set_reg(reg, val);
                     }
-                }
-                (0x6, 0x5) => {
-                    if cfg!(feature = "modern-ls") {
-                        let mut addr = self.idxr as usize;
-
-                        for reg in 0..=x {
-                            let val = self.read_byte(addr);
-                            self.set_reg(reg, val);
-                            addr += 1;
-                        }
-                    } else {
-                        for reg in 0..=x {
-                            let val = self.read_byte(self.idxr as usize);
-                            self.set_reg(reg, val);
-                            self.idxr += 1;
-                        }
+                } else {
+                    for reg in 0..=x {
+                        let val = self.read_byte(self.idxr as usize)?;
+                        self.set_reg(reg, val);
+                        self.idxr += 1;
                     }
                 }
-                _ => panic!("invalid instruction: {inst:#X}"),
-            },
-            _ => panic!("invalid instruction: {inst:#X}"),
+            }
+            Op::Invalid(inst) => return Err(Fault::InvalidInstruction(inst)),
         }
 
-        if self.dtr > 0 {
-            self.dtr -= 1;
-        }
+        Ok(())
+    }
 
-        if self.str > 0 {
-            self.str -= 1;
-        }
+    /// Decrement the delay and sound timers, sounding the buzzer while the sound timer is active,
+    /// and end the frame of the display.
+    fn tick_timers(&mut self) {
+        self.dtr = self.dtr.saturating_sub(1);
+        self.str = self.str.saturating_sub(1);
+        self.beeper.set_playing(self.str > 0);
+        self.display.end_frame();
     }
 
-    /// Execute the program.
-    pub fn execute_program(&mut self, prog: &[u8], step: bool, no_display: bool) {
+    /// Execute the program, running `ipf` instructions per frame.
+    ///
+    /// When falling behind, up to `frame_skip` consecutive frames are executed without being
+    /// presented to catch up.
+    ///
+    /// The program is reset with F5, and reloaded whenever it changes if it is watched. Execution
+    /// stops at the first fault.
+    pub fn execute_program(
+        &mut self,
+        prog: &[u8],
+        event_pump: &mut EventPump,
+        options: &RunOptions,
+    ) -> Result<(), Fault> {
+        let RunOptions {
+            ipf,
+            frame_skip,
+            step,
+            no_display,
+            ..
+        } = *options;
+        let mut speed = options.speed;
+        let mut paused = options.paused;
+        let mut fast_forward = false;
+        let mut prog = prog.to_vec();
+        let mut watcher = options.watch.clone().map(Watcher::new);
+
         // Load the program into memory.
-        self.load_program(prog);
+        self.load_program(&prog)?;
 
         if !no_display {
             // Render the initial, unmanipulated display.
             self.display.render();
         }
 
-        loop {
-            let event = self.event_pump.wait_event();
-            let inst = self.next_inst();
-
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    scancode: Some(Scancode::Escape),
-                    ..
-                } => break,
-                Event::KeyDown {
-                    scancode: Some(Scancode::N),
-                    ..
-                } if step => {
-                    self.execute_instruction(inst);
+        let mut next_frame = Instant::now();
+        let mut last_present = Instant::now();
+        let mut skipped = 0;
+
+        // Performance statistics for the heads-up display.
+        let mut stats_start = Instant::now();
+        let (mut frames, mut insts) = (0u32, 0u32);
+        let (mut fps, mut ips) = (0.0, 0.0);
+
+        'running: loop {
+            // In step mode, block until the next event since nothing happens without input anyway.
+            let blocking_event = step.then(|| event_pump.wait_event());
+            let mut advance = false;
+            let mut reset = false;
+
+            for event in blocking_event.into_iter().chain(event_pump.poll_iter()) {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        scancode: Some(Scancode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::N),
+                        ..
+                    } if step || paused => advance = true,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::P),
+                        repeat: false,
+                        ..
+                    } => {
+                        paused = !paused;
+                        self.beeper.set_playing(false);
+                        self.display
+                            .osd()
+                            .show_message(if paused { "Paused" } else { "Resumed" });
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F5),
+                        repeat: false,
+                        ..
+                    } => reset = true,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Equals | Scancode::KpPlus),
+                        ..
+                    } => {
+                        speed = speed.faster();
+                        self.display.osd().show_message(format!("Speed {speed}"));
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Minus | Scancode::KpMinus),
+                        ..
+                    } => {
+                        speed = speed.slower();
+                        self.display.osd().show_message(format!("Speed {speed}"));
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Tab),
+                        ..
+                    } => fast_forward = true,
+                    Event::KeyUp {
+                        scancode: Some(Scancode::Tab),
+                        ..
+                    } => fast_forward = false,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F1),
+                        repeat: false,
+                        ..
+                    } => {
+                        self.display.osd().toggle_hud();
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F2),
+                        repeat: false,
+                        ..
+                    } => {
+                        let name = self.display.cycle_palette().name;
+                        self.display.osd().show_message(format!("Palette: {name}"));
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F3),
+                        repeat: false,
+                        ..
+                    } => {
+                        let names: Vec<_> = self
+                            .display
+                            .cycle_filters()
+                            .iter()
+                            .map(|filter| filter.name())
+                            .collect();
+                        let names = if names.is_empty() {
+                            "none".to_string()
+                        } else {
+                            names.join(", ")
+                        };
+                        self.display.osd().show_message(format!("Filters: {names}"));
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F11),
+                        repeat: false,
+                        ..
+                    } => self.display.toggle_fullscreen(),
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Return),
+                        keymod,
+                        repeat: false,
+                        ..
+                    } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                        self.display.toggle_fullscreen();
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                        ..
+                    } => self.display.invalidate(),
+                    Event::KeyDown {
+                        scancode: Some(scancode),
+                        ..
+                    } => self.keyboard.press_key(scancode),
+                    Event::KeyUp {
+                        scancode: Some(scancode),
+                        ..
+                    } => self.keyboard.release_key(scancode),
+                    _ => {}
+                }
+            }
+
+            if reset {
+                self.reset(&prog, &options.preserve)?;
+                self.display.osd().show_message("Reset");
+            }
+
+            match watcher.as_mut().and_then(Watcher::poll) {
+                Some(Ok(new_prog)) => match self.reset(&new_prog, &options.preserve) {
+                    Ok(()) => {
+                        prog = new_prog;
+                        self.display.osd().show_message("Reloaded");
+                    }
+                    Err(fault) => {
+                        eprintln!("unable to reload the program: {fault}");
+                        self.display.osd().show_message("Reload failed");
+                    }
+                },
+                Some(Err(e)) => {
+                    eprintln!("{e}");
+                    self.display.osd().show_message("Reload failed");
+                }
+                None => {}
+            }
+
+            if step {
+                // Each step executes a single instruction and counts as a frame of its own.
+                if advance {
+                    self.execute_next()?;
+                    self.tick_timers();
+                    insts += 1;
                 }
-                Event::KeyDown {
-                    scancode: Some(scancode),
-                    ..
-                } => self.keyboard.press_key(scancode),
-                Event::KeyUp {
-                    scancode: Some(scancode),
-                    ..
-                } => self.keyboard.release_key(scancode),
-                _ => {}
+            } else if !paused || advance {
+                self.run_frame(ipf)?;
+                insts += ipf;
+
+                // Don't keep beeping after advancing a single frame.
+                if paused {
+                    self.beeper.set_playing(false);
+                }
+            }
+
+            let elapsed = stats_start.elapsed();
+
+            if elapsed >= STATS_INTERVAL {
+                fps = f64::from(frames) / elapsed.as_secs_f64();
+                ips = f64::from(insts) / elapsed.as_secs_f64();
+                stats_start = Instant::now();
+                (frames, insts) = (0, 0);
             }
 
-            if !step {
-                self.execute_instruction(inst);
+            if self.display.osd().hud_enabled() {
+                let lines = self.hud_lines(fps, ips);
+                self.display.osd().set_hud(lines);
             }
 
-            if self.rerender && !no_display {
+            // While paused, events are still handled and the display refreshed in real time.
+            let current_speed = if paused {
+                Speed::default()
+            } else if fast_forward {
+                Speed::Uncapped
+            } else {
+                speed
+            };
+            let frame_duration = current_speed.frame_duration(FRAME_DURATION);
+            let late = !step && frame_duration.is_some_and(|d| Instant::now() > next_frame + d);
+
+            // Faster than real time, frames are presented at the normal frame rate at most.
+            let throttled = current_speed.is_fast() && last_present.elapsed() < FRAME_DURATION;
+
+            if late && skipped < frame_skip {
+                skipped += 1;
+            } else if !throttled && !no_display {
                 self.display.render();
+                last_present = Instant::now();
+                skipped = 0;
+                frames += 1;
             }
 
-            std::thread::sleep(Duration::from_millis(2));
+            match frame_duration {
+                _ if step => {}
+                Some(duration) => {
+                    next_frame += duration;
+
+                    match next_frame.checked_duration_since(Instant::now()) {
+                        Some(delay) => std::thread::sleep(delay),
+                        // Only try to catch up on as many frames as may be skipped.
+                        None if next_frame.elapsed() > duration * frame_skip => {
+                            next_frame = Instant::now();
+                        }
+                        None => {}
+                    }
+                }
+                None => next_frame = Instant::now(),
+            }
         }
+
+        self.beeper.set_playing(false);
+        Ok(())
+    }
+
+    /// The lines of the heads-up display.
+    fn hud_lines(&self, fps: f64, ips: f64) -> Vec<String> {
+        vec![
+            format!("FPS {fps:.0}"),
+            format!("IPS {ips:.0}"),
+            format!("PC {:03X} I {:03X}", self.pc, self.idxr),
+            format!("DT {} ST {}", self.dtr, self.str),
+        ]
     }
 
     /// Dump CPU state at the end of execution.
@@ -500,3 +1139,9 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod reference;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,469 @@
+//! Differential tests of single instructions against a reference model, written directly from the
+//! specification rather than from the decoder and the CPU, on random machine states with each
+//! platform's quirks and on each engine.
+
+use super::{tests::PLATFORMS, *};
+use crate::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH};
+use proptest::{collection::vec, option, prelude::*, sample::select};
+use std::array;
+
+/// The state of the machine as seen by programs, and the reference model executing instructions on
+/// it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Machine {
+    mem: Vec<u8>,
+    pc: u16,
+    i: u16,
+    v: [u8; 16],
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    hires: bool,
+
+    /// The pixels of the display row by row, at the current resolution.
+    pixels: Vec<bool>,
+    keys: [bool; 16],
+
+    /// The key pressed while waiting on `FX0A`, if any.
+    held_key: Option<u8>,
+
+    /// The keys already held while waiting on `FX0A` for a press, if waiting.
+    wait_keys: Option<u16>,
+}
+
+impl Machine {
+    /// Capture the state of a CPU.
+    fn of(cpu: &Cpu) -> Self {
+        let display = &cpu.display;
+
+        Self {
+            mem: cpu.mem.to_vec(),
+            pc: cpu.pc,
+            i: cpu.idxr,
+            v: cpu.gpvr,
+            stack: cpu.stack.to_vec(),
+            delay: cpu.dtr,
+            sound: cpu.str,
+            hires: display.width() > WIDTH,
+            pixels: (0..display.height())
+                .flat_map(|y| (0..display.width()).map(move |x| display.get_pixel(x, y)))
+                .collect(),
+            keys: array::from_fn(|key| cpu.keyboard.is_key_pressed(key)),
+            held_key: cpu.held_key,
+            wait_keys: cpu.wait_keys,
+        }
+    }
+
+    /// Create a CPU in this state.
+    fn cpu(&self, quirks: Quirks) -> Cpu {
+        let mut cpu = Cpu::headless(quirks);
+
+        cpu.mem.copy_from_slice(&self.mem);
+        cpu.pc = self.pc;
+        cpu.idxr = self.i;
+        cpu.gpvr = self.v;
+        cpu.stack = self.stack.iter().copied().collect();
+        cpu.dtr = self.delay;
+        cpu.str = self.sound;
+        cpu.display.set_hires(self.hires);
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                if self.pixels[y * self.width() + x] {
+                    cpu.display.toggle_pixel(x, y);
+                }
+            }
+        }
+
+        for (key, &pressed) in self.keys.iter().enumerate() {
+            if pressed {
+                cpu.keyboard.press_key(DEFAULT_KEYMAP[key]);
+            }
+        }
+
+        cpu.held_key = self.held_key;
+        cpu.wait_keys = self.wait_keys;
+        cpu
+    }
+
+    fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, Fault> {
+        self.mem.get(addr).copied().ok_or(Fault::OutOfBounds(addr))
+    }
+
+    fn write(&mut self, addr: usize, byte: u8) -> Result<(), Fault> {
+        *self.mem.get_mut(addr).ok_or(Fault::OutOfBounds(addr))? = byte;
+        Ok(())
+    }
+
+    fn skip_if(&mut self, cond: bool) {
+        if cond {
+            self.pc += 2;
+        }
+    }
+
+    /// Set VX to a result and VF to a flag, in that order, so that the flag wins when X is F.
+    fn set_with_flag(&mut self, x: usize, result: u8, flag: u8) {
+        self.v[x] = result;
+        self.v[0xF] = flag;
+    }
+
+    /// Fetch and execute one instruction.
+    fn step(&mut self, quirks: Quirks) -> Result<(), Fault> {
+        let pc = usize::from(self.pc);
+        let inst = u16::from_be_bytes([self.read(pc)?, self.read(pc + 1)?]);
+        self.pc += 2;
+
+        let x = usize::from(inst >> 8 & 0xF);
+        let y = usize::from(inst >> 4 & 0xF);
+        let n = usize::from(inst & 0xF);
+        let nn = (inst & 0xFF) as u8;
+        let nnn = inst & 0xFFF;
+        let (vx, vy) = (self.v[x], self.v[y]);
+
+        match (inst >> 12, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => self.pixels.fill(false),
+            (0x0, 0x0, 0xE, 0xE) => self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?,
+            (0x0, 0x0, 0xF, 0xE | 0xF) => {
+                self.hires = n == 0xF;
+                self.pixels = vec![false; self.width() * self.height()];
+            }
+            (0x1, ..) => self.pc = nnn,
+            (0x2, ..) => {
+                if self.stack.len() == 16 {
+                    return Err(Fault::StackOverflow);
+                }
+
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            (0x3, ..) => self.skip_if(vx == nn),
+            (0x4, ..) => self.skip_if(vx != nn),
+            (0x5, _, _, 0x0) => self.skip_if(vx == vy),
+            (0x6, ..) => self.v[x] = nn,
+            (0x7, ..) => self.v[x] = vx.wrapping_add(nn),
+            (0x8, _, _, 0x0) => self.v[x] = vy,
+            (0x8, _, _, 0x1) => self.v[x] = vx | vy,
+            (0x8, _, _, 0x2) => self.v[x] = vx & vy,
+            (0x8, _, _, 0x3) => self.v[x] = vx ^ vy,
+            (0x8, _, _, 0x4) => {
+                let sum = u16::from(vx) + u16::from(vy);
+                self.set_with_flag(x, sum as u8, u8::from(sum > 0xFF));
+            }
+            (0x8, _, _, 0x5) => self.set_with_flag(x, vx.wrapping_sub(vy), u8::from(vx >= vy)),
+            (0x8, _, _, 0x6) => {
+                let val = if quirks.modern_shift { vx } else { vy };
+                self.set_with_flag(x, val >> 1, val & 1);
+            }
+            (0x8, _, _, 0x7) => self.set_with_flag(x, vy.wrapping_sub(vx), u8::from(vy >= vx)),
+            (0x8, _, _, 0xE) => {
+                let val = if quirks.modern_shift { vx } else { vy };
+                self.set_with_flag(x, val << 1, val >> 7);
+            }
+            (0x9, _, _, 0x0) => self.skip_if(vx != vy),
+            (0xA, ..) => self.i = nnn,
+            (0xB, ..) => self.pc = nnn + u16::from(self.v[if quirks.modern_jwo { x } else { 0 }]),
+            (0xC, ..) => {
+                assert_eq!(nn, 0, "random numbers can't be predicted");
+                self.v[x] = 0;
+            }
+            (0xD, ..) => self.draw(vx, vy, n)?,
+            (0xE, _, 0x9, 0xE) => self.skip_if(self.keys[usize::from(vx & 0xF)]),
+            (0xE, _, 0xA, 0x1) => self.skip_if(!self.keys[usize::from(vx & 0xF)]),
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.delay,
+            (0xF, _, 0x0, 0xA) => self.get_key(x, quirks),
+            (0xF, _, 0x1, 0x5) => self.delay = vx,
+            (0xF, _, 0x1, 0x8) => self.sound = vx,
+            (0xF, _, 0x1, 0xE) => {
+                self.i = self.i.wrapping_add(u16::from(vx));
+
+                // VF is only ever set, when I ends up outside of memory.
+                if usize::from(self.i) >= MEM_SIZE {
+                    self.v[0xF] = 1;
+                }
+            }
+            (0xF, _, 0x2, 0x9) => self.i = FONT_START as u16 + u16::from(vx & 0xF) * 5,
+            (0xF, _, 0x3, 0x3) => {
+                for (offset, digit) in [vx / 100, vx / 10 % 10, vx % 10].into_iter().enumerate() {
+                    self.write(usize::from(self.i) + offset, digit)?;
+                }
+            }
+            (0xF, _, 0x5, 0x5) => {
+                for reg in 0..=x {
+                    self.write(usize::from(self.i) + reg, self.v[reg])?;
+                }
+
+                if !quirks.modern_ls {
+                    self.i += x as u16 + 1;
+                }
+            }
+            (0xF, _, 0x6, 0x5) => {
+                for reg in 0..=x {
+                    self.v[reg] = self.read(usize::from(self.i) + reg)?;
+                }
+
+                if !quirks.modern_ls {
+                    self.i += x as u16 + 1;
+                }
+            }
+            _ => return Err(Fault::InvalidInstruction(inst)),
+        }
+
+        Ok(())
+    }
+
+    /// Draw a sprite from I at (VX, VY) wrapped to the display, clipping it at the edges. In the
+    /// high resolution mode, a height of 0 draws a 16x16 sprite.
+    fn draw(&mut self, vx: u8, vy: u8, n: usize) -> Result<(), Fault> {
+        let (width, height) = (self.width(), self.height());
+        let (x0, y0) = (usize::from(vx) % width, usize::from(vy) % height);
+        let (cols, rows) = if n == 0 && self.hires {
+            (16, 16)
+        } else {
+            (8, n)
+        };
+
+        self.v[0xF] = 0;
+
+        for y in (y0..y0 + rows).take_while(|&y| y < height) {
+            let addr = usize::from(self.i) + (y - y0) * cols / 8;
+            let sprite = (addr..addr + cols / 8)
+                .map(|addr| self.read(addr))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for col in 0..cols.min(width - x0) {
+                if sprite[col / 8] >> (7 - col % 8) & 1 == 1 {
+                    let pixel = &mut self.pixels[y * width + x0 + col];
+
+                    self.v[0xF] |= u8::from(*pixel);
+                    *pixel = !*pixel;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for a key to be pressed and released, or only pressed with the `key_wait_press` quirk,
+    /// by executing this instruction again until then. A press only counts for a key that wasn't
+    /// already held when the wait started.
+    fn get_key(&mut self, x: usize, quirks: Quirks) {
+        if quirks.key_wait_press {
+            let is_held = |keys: u16, key: usize| keys >> key & 1 == 1;
+            let now = (0..16)
+                .filter(|&key| self.keys[key])
+                .fold(0, |keys, key| keys | 1 << key);
+            let held = self.wait_keys.unwrap_or(now);
+
+            if let Some(key) = (0..16).find(|&key| self.keys[key] && !is_held(held, key)) {
+                self.wait_keys = None;
+                self.v[x] = key as u8;
+            } else {
+                self.wait_keys = Some(held & now);
+                self.pc -= 2;
+            }
+
+            return;
+        }
+
+        let pressed = self.keys.iter().position(|&pressed| pressed);
+
+        match (self.held_key, pressed) {
+            (None, Some(key)) => self.held_key = Some(key as u8),
+            (Some(key), _) if !self.keys[usize::from(key)] => {
+                self.held_key = None;
+                self.v[x] = key;
+                return;
+            }
+            _ => {}
+        }
+
+        self.pc -= 2;
+    }
+}
+
+/// Each instruction as the bits under a mask, with the remaining bits random.
+const INSTRUCTIONS: &[(u16, u16)] = &[
+    (0xFFFF, 0x00E0),
+    (0xFFFF, 0x00EE),
+    (0xFFFF, 0x00FE),
+    (0xFFFF, 0x00FF),
+    (0xF000, 0x1000),
+    (0xF000, 0x2000),
+    (0xF000, 0x3000),
+    (0xF000, 0x4000),
+    (0xF00F, 0x5000),
+    (0xF000, 0x6000),
+    (0xF000, 0x7000),
+    (0xF00F, 0x8000),
+    (0xF00F, 0x8001),
+    (0xF00F, 0x8002),
+    (0xF00F, 0x8003),
+    (0xF00F, 0x8004),
+    (0xF00F, 0x8005),
+    (0xF00F, 0x8006),
+    (0xF00F, 0x8007),
+    (0xF00F, 0x800E),
+    (0xF00F, 0x9000),
+    (0xF000, 0xA000),
+    (0xF000, 0xB000),
+    (0xF0FF, 0xC000),
+    (0xF000, 0xD000),
+    (0xF0FF, 0xE09E),
+    (0xF0FF, 0xE0A1),
+    (0xF0FF, 0xF007),
+    (0xF0FF, 0xF00A),
+    (0xF0FF, 0xF015),
+    (0xF0FF, 0xF018),
+    (0xF0FF, 0xF01E),
+    (0xF0FF, 0xF029),
+    (0xF0FF, 0xF033),
+    (0xF0FF, 0xF055),
+    (0xF0FF, 0xF065),
+];
+
+/// Any instruction, except for random numbers, which can't be predicted, and `5XYN` and `9XYN`
+/// with N other than 0, which the specification leaves undefined.
+fn instruction() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        4 => (select(INSTRUCTIONS), any::<u16>())
+            .prop_map(|((mask, bits), random)| bits | random & !mask),
+        1 => any::<u16>().prop_filter("unpredictable or undefined", |&inst| {
+            !matches!(inst & 0xF0FF, 0xC001..=0xC0FF)
+                && !matches!(inst & 0xF00F, 0x5001..=0x500F | 0x9001..=0x900F)
+        }),
+    ]
+}
+
+/// An address anywhere in memory or near its end, where accesses run past it.
+fn address() -> impl Strategy<Value = u16> {
+    const END: u16 = MEM_SIZE as u16;
+
+    prop_oneof![0..END, END - 0x20..END + 0x10]
+}
+
+prop_compose! {
+    fn machine()(
+        // Generated a word and a row at a time, which is much faster than a byte or a pixel.
+        words in vec(any::<u64>(), MEM_SIZE / 8),
+        pc in address(),
+        i in address(),
+        // Small values are likely to be equal, and are valid keys.
+        v in proptest::array::uniform16(prop_oneof![any::<u8>(), 0..0x10u8]),
+        stack in vec(0..MEM_SIZE as u16, 0..=16),
+        delay in any::<u8>(),
+        sound in any::<u8>(),
+        hires in any::<bool>(),
+        rows in vec(any::<u128>(), HIRES_HEIGHT),
+        keys in prop_oneof![Just([false; 16]), any::<[bool; 16]>()],
+        held_key in option::of(0..0x10u8),
+        wait_keys in option::of(any::<u16>()),
+    ) -> Machine {
+        let (width, height) = if hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (WIDTH, HEIGHT) };
+
+        Machine {
+            mem: words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+            pc,
+            i,
+            v,
+            stack,
+            delay,
+            sound,
+            hires,
+            pixels: rows[..height]
+                .iter()
+                .flat_map(|row| (0..width).map(move |x| row >> x & 1 == 1))
+                .collect(),
+            keys,
+            held_key,
+            wait_keys,
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2048))]
+
+    #[test]
+    fn matches_reference_model(
+        platform in select(PLATFORMS.to_vec()),
+        key_wait_press in any::<bool>(),
+        mut machine in machine(),
+        inst in instruction(),
+    ) {
+        let quirks = Quirks { key_wait_press, ..platform.quirks() };
+        let pc = usize::from(machine.pc);
+
+        if let Some(bytes) = machine.mem.get_mut(pc..pc + 2) {
+            bytes.copy_from_slice(&inst.to_be_bytes());
+        }
+
+        // The JIT leaves clearing the display to the interpreter, so the instruction makes a block
+        // of its own that is compiled.
+        if let Some(bytes) = machine.mem.get_mut(pc + 2..pc + 4) {
+            bytes.copy_from_slice(&[0x00, 0xE0]);
+        }
+
+        let mut stepped = machine.clone();
+        let expected = stepped.step(quirks);
+        let mut cpu = machine.cpu(quirks);
+
+        prop_assert_eq!(cpu.execute_next(), expected, "{:04X} on {:?}", inst, platform);
+
+        // Where a faulting program stopped doesn't matter, as it can't continue.
+        if expected.is_ok() {
+            prop_assert_eq!(Machine::of(&cpu), stepped.clone(), "{:04X} on {:?}", inst, platform);
+        }
+
+        // A frame of the instruction alone, after which the timers count down.
+        stepped.delay = stepped.delay.saturating_sub(1);
+        stepped.sound = stepped.sound.saturating_sub(1);
+
+        let engines = [
+            Engine::Interpreter,
+            Engine::Cached,
+            #[cfg(target_arch = "x86_64")]
+            Engine::Jit,
+        ];
+
+        for engine in engines {
+            let mut cpu = machine.cpu(quirks);
+            cpu.set_engine(engine);
+
+            prop_assert_eq!(
+                cpu.run_frame(1),
+                expected,
+                "{:04X} on {:?} with {}",
+                inst,
+                platform,
+                engine
+            );
+
+            if expected.is_ok() {
+                prop_assert_eq!(
+                    Machine::of(&cpu),
+                    stepped.clone(),
+                    "{:04X} on {:?} with {}",
+                    inst,
+                    platform,
+                    engine
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use super::*;
+use crate::quirks::Platform;
+
+/// The platforms whose quirks every instruction is tested with.
+pub(super) const PLATFORMS: [Platform; 7] = [
+    Platform::OriginalChip8,
+    Platform::HybridVip,
+    Platform::ModernChip8,
+    Platform::Chip48,
+    Platform::SuperChip1,
+    Platform::SuperChip,
+    Platform::XoChip,
+];
+
+/// Create a CPU with the font loaded and the program counter at the start of the program.
+fn cpu(quirks: Quirks) -> Cpu {
+    let mut cpu = Cpu::headless(quirks);
+    cpu.load_program(&[]).expect("empty program fits");
+    cpu
+}
+
+/// Run a test with a fresh CPU for each platform.
+fn for_each_platform(test: impl Fn(Platform, Cpu)) {
+    for platform in PLATFORMS {
+        test(platform, cpu(platform.quirks()));
+    }
+}
+
+/// Place an instruction at the program counter and execute it.
+fn exec(cpu: &mut Cpu, inst: u16) {
+    let pc = cpu.pc as usize;
+    cpu.mem[pc..pc + 2].copy_from_slice(&inst.to_be_bytes());
+    cpu.execute_next().expect("instruction doesn't fault");
+}
+
+/// Execute an instruction on registers set to the given values, returning the registers after.
+fn exec_with_regs(cpu: &mut Cpu, regs: &[(usize, u8)], inst: u16) -> [u8; 16] {
+    for &(reg, val) in regs {
+        cpu.gpvr[reg] = val;
+    }
+
+    exec(cpu, inst);
+    cpu.gpvr
+}
+
+/// Press or release a CHIP-8 key.
+fn set_key(cpu: &mut Cpu, key: usize, pressed: bool) {
+    if pressed {
+        cpu.keyboard.press_key(DEFAULT_KEYMAP[key]);
+    } else {
+        cpu.keyboard.release_key(DEFAULT_KEYMAP[key]);
+    }
+}
+
+#[test]
+fn clear_display() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.display.toggle_pixel(3, 4);
+        exec(&mut cpu, 0x00E0);
+
+        assert!(!cpu.display.get_pixel(3, 4), "{platform:?}");
+        assert_eq!(cpu.pc, 0x202, "{platform:?}");
+    });
+}
+
+#[test]
+fn call_and_return() {
+    for_each_platform(|platform, mut cpu| {
+        exec(&mut cpu, 0x2ABC);
+        assert_eq!(cpu.pc, 0xABC, "{platform:?}");
+        assert_eq!(cpu.stack.as_slice(), [0x202], "{platform:?}");
+
+        exec(&mut cpu, 0x00EE);
+        assert_eq!(cpu.pc, 0x202, "{platform:?}");
+        assert!(cpu.stack.is_empty(), "{platform:?}");
+    });
+}
+
+#[test]
+fn switch_resolution() {
+    for_each_platform(|platform, mut cpu| {
+        exec(&mut cpu, 0x00FF);
+        assert_eq!(
+            (cpu.display.width(), cpu.display.height()),
+            (128, 64),
+            "{platform:?}"
+        );
+
+        exec(&mut cpu, 0x00FE);
+        assert_eq!(
+            (cpu.display.width(), cpu.display.height()),
+            (64, 32),
+            "{platform:?}"
+        );
+    });
+}
+
+#[test]
+fn jump() {
+    for_each_platform(|platform, mut cpu| {
+        exec(&mut cpu, 0x1ABC);
+        assert_eq!(cpu.pc, 0xABC, "{platform:?}");
+    });
+}
+
+#[test]
+fn jump_with_offset() {
+    for_each_platform(|platform, mut cpu| {
+        exec_with_regs(&mut cpu, &[(0x0, 0x10), (0x3, 0x20)], 0xB345);
+
+        // The modern variant adds VX, where X is the highest nibble of the address.
+        let expected = if platform.quirks().modern_jwo {
+            0x365
+        } else {
+            0x355
+        };
+        assert_eq!(cpu.pc, expected, "{platform:?}");
+    });
+}
+
+#[test]
+fn skips() {
+    // The instruction, the values of V1 and V2, and whether it skips.
+    let cases = [
+        (0x3142, 0x42, 0x00, true),
+        (0x3142, 0x41, 0x00, false),
+        (0x4142, 0x42, 0x00, false),
+        (0x4142, 0x41, 0x00, true),
+        (0x5120, 0x42, 0x42, true),
+        (0x5120, 0x42, 0x43, false),
+        (0x9120, 0x42, 0x42, false),
+        (0x9120, 0x42, 0x43, true),
+    ];
+
+    for (inst, v1, v2, skips) in cases {
+        for_each_platform(|platform, mut cpu| {
+            exec_with_regs(&mut cpu, &[(0x1, v1), (0x2, v2)], inst);
+
+            let expected = if skips { 0x204 } else { 0x202 };
+            assert_eq!(cpu.pc, expected, "{inst:04X} on {platform:?}");
+        });
+    }
+}
+
+#[test]
+fn skip_on_key() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.gpvr[0x1] = 0xA;
+
+        exec(&mut cpu, 0xE19E);
+        assert_eq!(cpu.pc, 0x202, "{platform:?}");
+        exec(&mut cpu, 0xE1A1);
+        assert_eq!(cpu.pc, 0x206, "{platform:?}");
+
+        set_key(&mut cpu, 0xA, true);
+
+        exec(&mut cpu, 0xE19E);
+        assert_eq!(cpu.pc, 0x20A, "{platform:?}");
+        exec(&mut cpu, 0xE1A1);
+        assert_eq!(cpu.pc, 0x20C, "{platform:?}");
+
+        // Only the low nibble selects the key.
+        cpu.gpvr[0x1] = 0xFA;
+        exec(&mut cpu, 0xE19E);
+        assert_eq!(cpu.pc, 0x210, "{platform:?}");
+    });
+}
+
+#[test]
+fn set_and_add_immediate() {
+    for_each_platform(|platform, mut cpu| {
+        let regs = exec_with_regs(&mut cpu, &[], 0x6142);
+        assert_eq!(regs[0x1], 0x42, "{platform:?}");
+
+        // 7XNN wraps around without touching the flag.
+        let regs = exec_with_regs(&mut cpu, &[(0xF, 0x55)], 0x71C0);
+        assert_eq!(regs[0x1], 0x02, "{platform:?}");
+        assert_eq!(regs[0xF], 0x55, "{platform:?}");
+    });
+}
+
+#[test]
+fn copy_and_logic() {
+    // The instruction, the values of V1 and V2, and the resulting V1.
+    let cases = [
+        (0x8120, 0x0F, 0x3C, 0x3C),
+        (0x8121, 0x0F, 0x3C, 0x3F),
+        (0x8122, 0x0F, 0x3C, 0x0C),
+        (0x8123, 0x0F, 0x3C, 0x33),
+    ];
+
+    for (inst, v1, v2, result) in cases {
+        for_each_platform(|platform, mut cpu| {
+            let regs = exec_with_regs(&mut cpu, &[(0x1, v1), (0x2, v2), (0xF, 0x55)], inst);
+
+            assert_eq!(regs[0x1], result, "{inst:04X} on {platform:?}");
+            assert_eq!(regs[0x2], v2, "{inst:04X} on {platform:?}");
+            assert_eq!(regs[0xF], 0x55, "{inst:04X} on {platform:?}");
+        });
+    }
+}
+
+#[test]
+fn add_with_carry() {
+    // The values of V1 and V2, the sum and the carry.
+    let cases = [
+        (0x10, 0x20, 0x30, 0),
+        (0xF0, 0x20, 0x10, 1),
+        (0xFF, 0x01, 0x00, 1),
+        (0x80, 0x7F, 0xFF, 0),
+    ];
+
+    for (v1, v2, sum, carry) in cases {
+        for_each_platform(|platform, mut cpu| {
+            // A carry left over from before has to be cleared.
+            let regs = exec_with_regs(&mut cpu, &[(0x1, v1), (0x2, v2), (0xF, 1)], 0x8124);
+
+            assert_eq!(regs[0x1], sum, "{v1:#X} + {v2:#X} on {platform:?}");
+            assert_eq!(regs[0xF], carry, "{v1:#X} + {v2:#X} on {platform:?}");
+        });
+    }
+}
+
+#[test]
+fn subtract_with_borrow() {
+    // The values of V1 and V2, V1 - V2, and whether there was no borrow.
+    let cases = [
+        (0x30, 0x10, 0x20, 1),
+        (0x10, 0x30, 0xE0, 0),
+        (0x42, 0x42, 0x00, 1),
+        (0x00, 0x01, 0xFF, 0),
+    ];
+
+    for (v1, v2, diff, no_borrow) in cases {
+        for_each_platform(|platform, mut cpu| {
+            let regs = exec_with_regs(&mut cpu, &[(0x1, v1), (0x2, v2), (0xF, 0x55)], 0x8125);
+            assert_eq!(regs[0x1], diff, "{v1:#X} - {v2:#X} on {platform:?}");
+            assert_eq!(regs[0xF], no_borrow, "{v1:#X} - {v2:#X} on {platform:?}");
+
+            // 8XY7 subtracts the other way around.
+            let regs = exec_with_regs(&mut cpu, &[(0x1, v2), (0x2, v1), (0xF, 0x55)], 0x8127);
+            assert_eq!(regs[0x1], diff, "{v1:#X} - {v2:#X} on {platform:?}");
+            assert_eq!(regs[0xF], no_borrow, "{v1:#X} - {v2:#X} on {platform:?}");
+        });
+    }
+}
+
+#[test]
+fn shifts() {
+    for_each_platform(|platform, mut cpu| {
+        let modern = platform.quirks().modern_shift;
+
+        // The legacy variant shifts VY into VX, leaving VY as it is.
+        let regs = exec_with_regs(&mut cpu, &[(0x1, 0x81), (0x2, 0x42)], 0x8126);
+        let (result, flag) = if modern { (0x40, 1) } else { (0x21, 0) };
+        assert_eq!((regs[0x1], regs[0xF]), (result, flag), "{platform:?}");
+        assert_eq!(regs[0x2], 0x42, "{platform:?}");
+
+        let regs = exec_with_regs(&mut cpu, &[(0x1, 0x81), (0x2, 0x42)], 0x812E);
+        let (result, flag) = if modern { (0x02, 1) } else { (0x84, 0) };
+        assert_eq!((regs[0x1], regs[0xF]), (result, flag), "{platform:?}");
+        assert_eq!(regs[0x2], 0x42, "{platform:?}");
+    });
+}
+
+#[test]
+fn flag_wins_as_destination() {
+    // With VF as the destination, it holds the flag rather than the result.
+    let cases = [
+        (0x8F14, 0xFF, 0x01, 1),
+        (0x8F14, 0x10, 0x01, 0),
+        (0x8F15, 0x10, 0x01, 1),
+        (0x8F15, 0x01, 0x10, 0),
+        (0x8F17, 0x01, 0x10, 1),
+        (0x8F17, 0x10, 0x01, 0),
+    ];
+
+    for (inst, vf, v1, flag) in cases {
+        for_each_platform(|platform, mut cpu| {
+            let regs = exec_with_regs(&mut cpu, &[(0xF, vf), (0x1, v1)], inst);
+            assert_eq!(
+                regs[0xF], flag,
+                "{inst:04X} with VF = {vf:#X} on {platform:?}"
+            );
+        });
+    }
+
+    for_each_platform(|platform, mut cpu| {
+        // 0x81 shifted either way has a flag of 1.
+        let regs = exec_with_regs(&mut cpu, &[(0xF, 0x81)], 0x8FF6);
+        assert_eq!(regs[0xF], 1, "8FF6 on {platform:?}");
+
+        let regs = exec_with_regs(&mut cpu, &[(0xF, 0x81)], 0x8FFE);
+        assert_eq!(regs[0xF], 1, "8FFE on {platform:?}");
+    });
+}
+
+#[test]
+fn flag_as_source() {
+    // With VF as the source operand, its value from before the instruction is used.
+    let cases = [
+        (0x81F4, 0xFF, 0x02, 0x01, 1),
+        (0x81F5, 0x01, 0x02, 0xFF, 0),
+        (0x81F7, 0x01, 0x03, 0x02, 1),
+    ];
+
+    for (inst, v1, vf, result, flag) in cases {
+        for_each_platform(|platform, mut cpu| {
+            let regs = exec_with_regs(&mut cpu, &[(0x1, v1), (0xF, vf)], inst);
+
+            assert_eq!(regs[0x1], result, "{inst:04X} on {platform:?}");
+            assert_eq!(regs[0xF], flag, "{inst:04X} on {platform:?}");
+        });
+    }
+}
+
+#[test]
+fn set_index() {
+    for_each_platform(|platform, mut cpu| {
+        exec(&mut cpu, 0xA123);
+        assert_eq!(cpu.idxr, 0x123, "{platform:?}");
+    });
+}
+
+#[test]
+fn random() {
+    for_each_platform(|platform, mut cpu| {
+        for _ in 0..32 {
+            let regs = exec_with_regs(&mut cpu, &[], 0xC10F);
+            assert_eq!(regs[0x1] & 0xF0, 0, "{platform:?}");
+
+            let regs = exec_with_regs(&mut cpu, &[], 0xC100);
+            assert_eq!(regs[0x1], 0, "{platform:?}");
+        }
+    });
+}
+
+#[test]
+fn draw() {
+    for_each_platform(|platform, mut cpu| {
+        // The font character 0 is a 4x5 ring.
+        cpu.idxr = FONT_START as u16;
+        exec_with_regs(&mut cpu, &[(0x1, 2), (0x2, 3), (0xF, 1)], 0xD125);
+
+        assert!(cpu.display.get_pixel(2, 3), "{platform:?}");
+        assert!(cpu.display.get_pixel(5, 7), "{platform:?}");
+        assert!(!cpu.display.get_pixel(3, 4), "{platform:?}");
+        assert!(!cpu.display.get_pixel(6, 3), "{platform:?}");
+        assert_eq!(cpu.gpvr[0xF], 0, "{platform:?}");
+
+        // Drawing again erases the sprite and reports the collision.
+        exec(&mut cpu, 0xD125);
+
+        assert!(!cpu.display.get_pixel(2, 3), "{platform:?}");
+        assert_eq!(cpu.gpvr[0xF], 1, "{platform:?}");
+    });
+}
+
+#[test]
+fn draw_wraps_and_clips() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.idxr = FONT_START as u16;
+
+        // The starting position wraps around the display.
+        exec_with_regs(&mut cpu, &[(0x1, 64 + 1), (0x2, 32 + 2)], 0xD125);
+        assert!(cpu.display.get_pixel(1, 2), "{platform:?}");
+
+        // The sprite itself is clipped at the edges.
+        exec(&mut cpu, 0x00E0);
+        exec_with_regs(&mut cpu, &[(0x1, 62), (0x2, 30)], 0xD125);
+        assert!(cpu.display.get_pixel(63, 30), "{platform:?}");
+        assert!(cpu.display.get_pixel(62, 31), "{platform:?}");
+        assert!(!cpu.display.get_pixel(0, 30), "{platform:?}");
+        assert!(!cpu.display.get_pixel(62, 0), "{platform:?}");
+    });
+}
+
+#[test]
+fn draw_with_flag_as_coordinate() {
+    for_each_platform(|platform, mut cpu| {
+        // The coordinate is read before the flag is reset.
+        cpu.idxr = FONT_START as u16;
+        exec_with_regs(&mut cpu, &[(0xF, 10), (0x1, 4)], 0xDF15);
+
+        assert!(cpu.display.get_pixel(10, 4), "{platform:?}");
+        assert_eq!(cpu.gpvr[0xF], 0, "{platform:?}");
+    });
+}
+
+#[test]
+fn draw_large_sprite() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.idxr = 0x300;
+        cpu.mem[0x300..0x320].fill(0xFF);
+
+        exec(&mut cpu, 0x00FF);
+        exec_with_regs(&mut cpu, &[(0x1, 0), (0x2, 0)], 0xD120);
+
+        assert!(cpu.display.get_pixel(15, 15), "{platform:?}");
+        assert!(!cpu.display.get_pixel(16, 0), "{platform:?}");
+        assert!(!cpu.display.get_pixel(0, 16), "{platform:?}");
+    });
+}
+
+#[test]
+fn timers() {
+    for_each_platform(|platform, mut cpu| {
+        exec_with_regs(&mut cpu, &[(0x1, 3), (0x2, 5)], 0xF115);
+        exec(&mut cpu, 0xF218);
+        assert_eq!((cpu.dtr, cpu.str), (3, 5), "{platform:?}");
+
+        cpu.tick_timers();
+        let regs = exec_with_regs(&mut cpu, &[], 0xF307);
+        assert_eq!(regs[0x3], 2, "{platform:?}");
+        assert_eq!(cpu.str, 4, "{platform:?}");
+    });
+}
+
+#[test]
+fn wait_for_key() {
+    for key_wait_press in [false, true] {
+        for_each_platform(|platform, mut cpu| {
+            cpu.quirks.key_wait_press = key_wait_press;
+
+            // Without a key, the instruction is executed again.
+            exec(&mut cpu, 0xF10A);
+            assert_eq!(cpu.pc, 0x200, "{platform:?}");
+
+            set_key(&mut cpu, 0x7, true);
+            exec(&mut cpu, 0xF10A);
+
+            if !key_wait_press {
+                // The key has to be released again.
+                assert_eq!(cpu.pc, 0x200, "{platform:?}");
+
+                set_key(&mut cpu, 0x7, false);
+                exec(&mut cpu, 0xF10A);
+            }
+
+            assert_eq!(cpu.pc, 0x202, "{platform:?}");
+            assert_eq!(cpu.gpvr[0x1], 0x7, "{platform:?}");
+        });
+    }
+}
+
+#[test]
+fn wait_for_new_key_press() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.quirks.key_wait_press = true;
+
+        // A key held since before the wait doesn't count as a press, however often it runs.
+        set_key(&mut cpu, 0x3, true);
+        for _ in 0..3 {
+            exec(&mut cpu, 0xF10A);
+            assert_eq!(cpu.pc, 0x200, "{platform:?}");
+        }
+
+        // Another key pressed while waiting does.
+        set_key(&mut cpu, 0x9, true);
+        exec(&mut cpu, 0xF10A);
+        assert_eq!((cpu.pc, cpu.gpvr[0x1]), (0x202, 0x9), "{platform:?}");
+
+        // Both keys are still held when the next wait starts, so it waits for the next press.
+        exec(&mut cpu, 0xF20A);
+        assert_eq!(cpu.pc, 0x202, "{platform:?}");
+
+        // A key released while waiting is pressed again.
+        set_key(&mut cpu, 0x3, false);
+        exec(&mut cpu, 0xF20A);
+        assert_eq!(cpu.pc, 0x202, "{platform:?}");
+
+        set_key(&mut cpu, 0x3, true);
+        exec(&mut cpu, 0xF20A);
+        assert_eq!((cpu.pc, cpu.gpvr[0x2]), (0x204, 0x3), "{platform:?}");
+    });
+}
+
+#[test]
+fn add_to_index() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.idxr = 0x123;
+        let regs = exec_with_regs(&mut cpu, &[(0x1, 0x45)], 0xF11E);
+
+        assert_eq!(cpu.idxr, 0x168, "{platform:?}");
+        assert_eq!(regs[0xF], 0, "{platform:?}");
+    });
+}
+
+#[test]
+fn font_character() {
+    for_each_platform(|platform, mut cpu| {
+        for digit in 0..16 {
+            exec_with_regs(&mut cpu, &[(0x1, digit)], 0xF129);
+
+            let addr = cpu.idxr as usize;
+            assert_eq!(addr, FONT_START + digit as usize * 5, "{platform:?}");
+            assert_eq!(
+                cpu.mem[addr..addr + 5],
+                FONT[digit as usize * 5..digit as usize * 5 + 5],
+                "{platform:?}"
+            );
+        }
+    });
+}
+
+#[test]
+fn binary_coded_decimal() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.idxr = 0x300;
+        exec_with_regs(&mut cpu, &[(0x1, 254)], 0xF133);
+
+        assert_eq!(cpu.mem[0x300..0x303], [2, 5, 4], "{platform:?}");
+        assert_eq!(cpu.idxr, 0x300, "{platform:?}");
+    });
+}
+
+#[test]
+fn store_and_load() {
+    for_each_platform(|platform, mut cpu| {
+        let modern = platform.quirks().modern_ls;
+
+        // The legacy variant leaves I after the last register.
+        cpu.idxr = 0x300;
+        exec_with_regs(&mut cpu, &[(0x0, 1), (0x1, 2), (0x2, 3), (0x3, 4)], 0xF255);
+        assert_eq!(cpu.mem[0x300..0x304], [1, 2, 3, 0], "{platform:?}");
+        assert_eq!(cpu.idxr, if modern { 0x300 } else { 0x303 }, "{platform:?}");
+
+        cpu.idxr = 0x300;
+        let regs = exec_with_regs(&mut cpu, &[(0x0, 0), (0x1, 0), (0x3, 9)], 0xF165);
+        assert_eq!(regs[0x0..0x4], [1, 2, 3, 9], "{platform:?}");
+        assert_eq!(cpu.idxr, if modern { 0x300 } else { 0x302 }, "{platform:?}");
+    });
+}
+
+#[test]
+fn program_too_large() {
+    let mut cpu = Cpu::headless(Quirks::default());
+
+    assert_eq!(
+        cpu.load_program(&[0; MEM_SIZE - PROG_START + 1]),
+        Err(Fault::ProgramTooLarge(MEM_SIZE - PROG_START + 1))
+    );
+}
+
+#[test]
+fn reset_with_program_too_large() {
+    let mut cpu = cpu(Quirks::default());
+    cpu.load_program(&[0x12, 0x00]).expect("program fits");
+    cpu.gpvr[0x3] = 0x42;
+    cpu.pc = 0x202;
+
+    assert_eq!(
+        cpu.reset(&[0; MEM_SIZE - PROG_START + 1], &[]),
+        Err(Fault::ProgramTooLarge(MEM_SIZE - PROG_START + 1))
+    );
+
+    // The running program is left as it was.
+    assert_eq!(cpu.mem[0x200..0x202], [0x12, 0x00]);
+    assert_eq!((cpu.gpvr[0x3], cpu.pc), (0x42, 0x202));
+
+    cpu.reset(&[0x00, 0xE0], &[]).expect("program fits");
+    assert_eq!(cpu.mem[0x200..0x202], [0x00, 0xE0]);
+    assert_eq!((cpu.gpvr[0x3], cpu.pc), (0, 0x200));
+}
+
+#[test]
+fn invalid_instruction() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.mem[0x200..0x202].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(
+            cpu.execute_next(),
+            Err(Fault::InvalidInstruction(0xFFFF)),
+            "{platform:?}"
+        );
+    });
+}
+
+#[test]
+fn stack_faults() {
+    for_each_platform(|platform, mut cpu| {
+        assert_eq!(
+            cpu.execute_op(Op::Return),
+            Err(Fault::StackUnderflow),
+            "{platform:?}"
+        );
+
+        for _ in 0..16 {
+            cpu.execute_op(Op::Call(0x200)).expect("stack has room");
+        }
+
+        assert_eq!(
+            cpu.execute_op(Op::Call(0x200)),
+            Err(Fault::StackOverflow),
+            "{platform:?}"
+        );
+    });
+}
+
+#[test]
+fn fetch_past_end_of_memory() {
+    for_each_platform(|platform, mut cpu| {
+        cpu.pc = 0xFFF;
+        assert_eq!(
+            cpu.execute_next(),
+            Err(Fault::OutOfBounds(0x1000)),
+            "{platform:?}"
+        );
+    });
+}
+
+#[test]
+fn memory_access_past_end_of_memory() {
+    // The instruction, and the first address past the end of memory it accesses.
+    let cases = [
+        (Op::Draw(0x0, 0x0, 5), 0x1000),
+        (Op::Bcd(0x0), 0x1000),
+        (Op::Store(0x3), 0x1000),
+        (Op::Load(0x3), 0x1000),
+    ];
+
+    for (op, addr) in cases {
+        for_each_platform(|platform, mut cpu| {
+            cpu.idxr = 0xFFE;
+            assert_eq!(
+                cpu.execute_op(op),
+                Err(Fault::OutOfBounds(addr)),
+                "{op:?} on {platform:?}"
+            );
+        });
+    }
+}
+
+/// A program overwriting the immediate of an instruction it already executed with `FX55`, and
+/// setting V1 to the new value once it runs again.
+const STORE_OVER_CODE: &[u8] = &[
+    0x61, 0x05, // 0x200: LD V1, 0x05
+    0x12, 0x06, // 0x202: JP 0x206
+    0x00, 0x00, // 0x204
+    0xA2, 0x01, // 0x206: LD I, 0x201
+    0x60, 0x07, // 0x208: LD V0, 0x07
+    0xF0, 0x55, // 0x20A: LD [I], V0
+    0x12, 0x00, // 0x20C: JP 0x200
+];
+
+/// A program overwriting a skip it already executed with the digits of `FX33`, so that it skips
+/// to setting V2 once it runs again.
+const BCD_OVER_CODE: &[u8] = &[
+    0x60, 0xC8, // 0x200: LD V0, 0xC8
+    0xA2, 0x05, // 0x202: LD I, 0x205
+    0x40, 0xC8, // 0x204: SNE V0, 0xC8, and SNE V0, 0x02 once written
+    0x12, 0x0A, // 0x206: JP 0x20A
+    0x62, 0x01, // 0x208: LD V2, 0x01
+    0xF0, 0x33, // 0x20A: LD B, V0
+    0x12, 0x04, // 0x20C: JP 0x204
+];
+
+/// A program mixing instructions the JIT compiles with calls, memory accesses and drawing,
+/// which it leaves to the interpreter.
+const MIXED_CODE: &[u8] = &[
+    0x60, 0x03, // 0x200: LD V0, 0x03
+    0x61, 0x05, // 0x202: LD V1, 0x05
+    0x80, 0x14, // 0x204: ADD V0, V1
+    0x22, 0x12, // 0x206: CALL 0x212
+    0xA3, 0x00, // 0x208: LD I, 0x300
+    0xF1, 0x55, // 0x20A: LD [I], V1
+    0xF2, 0x65, // 0x20C: LD V2, [I]
+    0xD0, 0x15, // 0x20E: DRW V0, V1, 0x5
+    0x12, 0x0E, // 0x210: JP 0x20E
+    0x70, 0x01, // 0x212: ADD V0, 0x01
+    0xF0, 0x29, // 0x214: LD F, V0
+    0x00, 0xEE, // 0x216: RET
+];
+
+/// The engines compared with the interpreter.
+const ENGINES: &[Engine] = &[
+    Engine::Cached,
+    #[cfg(target_arch = "x86_64")]
+    Engine::Jit,
+];
+
+/// The pixels of the display, row by row.
+fn pixels(cpu: &Cpu) -> Vec<bool> {
+    let (width, height) = (cpu.display.width(), cpu.display.height());
+    (0..width * height)
+        .map(|idx| cpu.display.get_pixel(idx % width, idx / width))
+        .collect()
+}
+
+/// Run a number of instructions of a program with an engine.
+fn run_with_engine(engine: Engine, prog: &[u8], instructions: u32) -> Cpu {
+    let mut cpu = Cpu::headless(Platform::ModernChip8.quirks());
+    cpu.set_engine(engine);
+    cpu.load_program(prog).expect("program fits");
+    cpu.run_frame(instructions).expect("program doesn't fault");
+    cpu
+}
+
+#[test]
+fn overwritten_code_runs_as_written() {
+    for &engine in std::iter::once(&Engine::Interpreter).chain(ENGINES) {
+        let cpu = run_with_engine(engine, STORE_OVER_CODE, 7);
+        assert_eq!(cpu.mem[0x201], 0x07, "{engine}");
+        assert_eq!(cpu.gpvr[0x1], 0x07, "{engine}");
+
+        let cpu = run_with_engine(engine, BCD_OVER_CODE, 9);
+        assert_eq!(cpu.mem[0x204..0x208], [0x40, 0x02, 0x00, 0x00], "{engine}");
+        assert_eq!(cpu.gpvr[0x2], 0x01, "{engine}");
+    }
+}
+
+#[test]
+fn engines_match_the_interpreter() {
+    let programs = [
+        ("store over code", STORE_OVER_CODE),
+        ("BCD over code", BCD_OVER_CODE),
+        ("mixed code", MIXED_CODE),
+    ];
+
+    for (name, prog) in programs {
+        // Instruction counts ending the frame within blocks and at their ends.
+        for instructions in [1, 5, 9, 10, 40, 101] {
+            let expected = run_with_engine(Engine::Interpreter, prog, instructions);
+
+            for &engine in ENGINES {
+                let cpu = run_with_engine(engine, prog, instructions);
+                let context = format!("{name}, {instructions} instructions on {engine}");
+
+                assert_eq!(cpu.pc, expected.pc, "{context}");
+                assert_eq!(cpu.gpvr, expected.gpvr, "{context}");
+                assert_eq!(cpu.idxr, expected.idxr, "{context}");
+                assert_eq!(cpu.stack, expected.stack, "{context}");
+                assert_eq!(cpu.mem, expected.mem, "{context}");
+                assert_eq!(pixels(&cpu), pixels(&expected), "{context}");
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+/// An instruction decoded into its kind and operands.
+///
+/// Registers are given by index, and addresses are 12 bits wide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// 00E0: Clear the display.
+    Clear,
+
+    /// 00EE: Return from a subroutine.
+    Return,
+
+    /// 00FE/00FF: Switch to the low or high resolution mode.
+    SetHires(bool),
+
+    /// 1NNN: Jump to NNN.
+    Jump(u16),
+
+    /// 2NNN: Call the subroutine at NNN.
+    Call(u16),
+
+    /// 3XNN: Skip if VX == NN.
+    SkipEq(usize, u8),
+
+    /// 4XNN: Skip if VX != NN.
+    SkipNe(usize, u8),
+
+    /// 5XY0: Skip if VX == VY.
+    SkipEqReg(usize, usize),
+
+    /// 6XNN: VX = NN.
+    Set(usize, u8),
+
+    /// 7XNN: VX += NN, without carry.
+    AddImm(usize, u8),
+
+    /// 8XY0: VX = VY.
+    Copy(usize, usize),
+
+    /// 8XY1: VX |= VY.
+    Or(usize, usize),
+
+    /// 8XY2: VX &= VY.
+    And(usize, usize),
+
+    /// 8XY3: VX ^= VY.
+    Xor(usize, usize),
+
+    /// 8XY4: VX += VY, with carry.
+    Add(usize, usize),
+
+    /// 8XY5: VX -= VY, with borrow.
+    Sub(usize, usize),
+
+    /// 8XY6: Shift right.
+    ShiftRight(usize, usize),
+
+    /// 8XY7: VX = VY - VX, with borrow.
+    SubReverse(usize, usize),
+
+    /// 8XYE: Shift left.
+    ShiftLeft(usize, usize),
+
+    /// 9XY0: Skip if VX != VY.
+    SkipNeReg(usize, usize),
+
+    /// ANNN: I = NNN.
+    SetIndex(u16),
+
+    /// BNNN: Jump to NNN plus a register.
+    JumpOffset(u16),
+
+    /// CXNN: VX = random & NN.
+    Random(usize, u8),
+
+    /// DXYN: Draw a sprite of height N at (VX, VY).
+    Draw(usize, usize, usize),
+
+    /// EX9E: Skip if the key in VX is pressed.
+    SkipKey(usize),
+
+    /// EXA1: Skip if the key in VX isn't pressed.
+    SkipNotKey(usize),
+
+    /// FX07: VX = the delay timer.
+    GetDelay(usize),
+
+    /// FX0A: Wait for a key and store it in VX.
+    GetKey(usize),
+
+    /// FX15: The delay timer = VX.
+    SetDelay(usize),
+
+    /// FX18: The sound timer = VX.
+    SetSound(usize),
+
+    /// FX1E: I += VX.
+    AddIndex(usize),
+
+    /// FX29: I = the address of the font character in VX.
+    Font(usize),
+
+    /// FX33: Store the decimal digits of VX at I.
+    Bcd(usize),
+
+    /// FX55: Store V0 to VX at I.
+    Store(usize),
+
+    /// FX65: Load V0 to VX from I.
+    Load(usize),
+
+    /// Anything else.
+    Invalid(u16),
+}
+
+impl Op {
+    /// Check whether the instruction ends a basic block, because execution may continue elsewhere
+    /// after it or it writes to memory, possibly overwriting the instructions after it.
+    pub const fn ends_block(self) -> bool {
+        matches!(
+            self,
+            Self::Return
+                | Self::Jump(_)
+                | Self::Call(_)
+                | Self::SkipEq(..)
+                | Self::SkipNe(..)
+                | Self::SkipEqReg(..)
+                | Self::SkipNeReg(..)
+                | Self::JumpOffset(_)
+                | Self::SkipKey(_)
+                | Self::SkipNotKey(_)
+                | Self::GetKey(_)
+                | Self::Invalid(_)
+        ) || self.writes_memory()
+    }
+
+    /// Check whether the instruction writes to memory.
+    pub const fn writes_memory(self) -> bool {
+        matches!(self, Self::Bcd(_) | Self::Store(_))
+    }
+}
+
+/// Decode an instruction.
+pub fn decode(inst: u16) -> Op {
+    // The highest nibble encodes the kind of instruction, the remaining nibbles or combinations of
+    // them registers, immediate numbers or addresses.
+    let x = ((inst >> 8) & 0xF) as usize;
+    let y = ((inst >> 4) & 0xF) as usize;
+    let n = (inst & 0xF) as usize;
+    let nn = (inst & 0xFF) as u8;
+    let nnn = inst & 0xFFF;
+
+    match inst >> 12 {
+        0x0 => match nnn {
+            0x0E0 => Op::Clear,
+            0x0EE => Op::Return,
+            0x0FE | 0x0FF => Op::SetHires(nnn == 0x0FF),
+            _ => Op::Invalid(inst),
+        },
+        0x1 => Op::Jump(nnn),
+        0x2 => Op::Call(nnn),
+        0x3 => Op::SkipEq(x, nn),
+        0x4 => Op::SkipNe(x, nn),
+        0x5 => Op::SkipEqReg(x, y),
+        0x6 => Op::Set(x, nn),
+        0x7 => Op::AddImm(x, nn),
+        0x8 => match n {
+            0x0 => Op::Copy(x, y),
+            0x1 => Op::Or(x, y),
+            0x2 => Op::And(x, y),
+            0x3 => Op::Xor(x, y),
+            0x4 => Op::Add(x, y),
+            0x5 => Op::Sub(x, y),
+            0x6 => Op::ShiftRight(x, y),
+            0x7 => Op::SubReverse(x, y),
+            0xE => Op::ShiftLeft(x, y),
+            _ => Op::Invalid(inst),
+        },
+        0x9 => Op::SkipNeReg(x, y),
+        0xA => Op::SetIndex(nnn),
+        0xB => Op::JumpOffset(nnn),
+        0xC => Op::Random(x, nn),
+        0xD => Op::Draw(x, y, n),
+        0xE => match nn {
+            0x9E => Op::SkipKey(x),
+            0xA1 => Op::SkipNotKey(x),
+            _ => Op::Invalid(inst),
+        },
+        0xF => match nn {
+            0x07 => Op::GetDelay(x),
+            0x0A => Op::GetKey(x),
+            0x15 => Op::SetDelay(x),
+            0x18 => Op::SetSound(x),
+            0x1E => Op::AddIndex(x),
+            0x29 => Op::Font(x),
+            0x33 => Op::Bcd(x),
+            0x55 => Op::Store(x),
+            0x65 => Op::Load(x),
+            _ => Op::Invalid(inst),
+        },
+        _ => Op::Invalid(inst),
+    }
+}
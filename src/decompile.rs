@@ -0,0 +1,399 @@
+use crate::{analyze::Analysis, cpu::PROG_START, disasm::opcode_class};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+/// The number of bytes of data that aren't sprites written per line.
+const BYTES_PER_LINE: usize = 8;
+
+/// A part of the program as laid out in the source.
+enum Item {
+    Inst(u16),
+    Byte(u8),
+}
+
+/// Turns an analyzed program back into Octo source that assembles to the same bytes.
+///
+/// Jump, call and `ANNN` targets get labels, backward jumps that nest properly become
+/// `loop ... again`, skips become `if ... then` and data is written as bytes, sprites as binary.
+pub struct Decompiler<'a> {
+    analysis: &'a Analysis,
+
+    /// The instructions and data bytes of the program, by address.
+    items: BTreeMap<usize, Item>,
+
+    /// The names of the addresses that are referenced.
+    labels: BTreeMap<usize, String>,
+
+    /// The loops, as the addresses of their start and of the jump back to it.
+    loops: BTreeMap<usize, usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(analysis: &'a Analysis) -> Self {
+        let end = PROG_START + analysis.prog_len;
+        let mut items = BTreeMap::new();
+        let mut addr = PROG_START;
+
+        // Lay out code where it was reached, and everything else as data.
+        while addr < end {
+            match analysis.insts.get(&addr) {
+                Some(&inst) if addr + 2 <= end => {
+                    items.insert(addr, Item::Inst(inst));
+                    addr += 2;
+                }
+                _ => {
+                    items.insert(addr, Item::Byte(analysis.byte(addr)));
+                    addr += 1;
+                }
+            }
+        }
+
+        let mut decompiler = Self {
+            analysis,
+            items,
+            labels: BTreeMap::new(),
+            loops: BTreeMap::new(),
+        };
+        decompiler.find_loops();
+        decompiler.name_labels();
+
+        decompiler
+    }
+
+    /// Find backward jumps that can be written as `loop ... again`, which have to nest properly.
+    fn find_loops(&mut self) {
+        let mut candidates: BTreeMap<usize, usize> = BTreeMap::new();
+
+        for (&addr, item) in &self.items {
+            if let Item::Inst(inst) = *item {
+                let target = (inst & 0xFFF) as usize;
+
+                if opcode_class(inst) == "1NNN"
+                    && target <= addr
+                    && matches!(self.items.get(&target), Some(Item::Inst(_)))
+                {
+                    // Of several jumps back to the same address, the last one makes the loop.
+                    candidates.insert(target, addr);
+                }
+            }
+        }
+
+        let mut open: Vec<usize> = Vec::new();
+
+        for (start, end) in candidates {
+            while open.last().is_some_and(|&open_end| open_end < start) {
+                open.pop();
+            }
+
+            if open.last().is_none_or(|&open_end| end < open_end) {
+                self.loops.insert(start, end);
+                open.push(end);
+            }
+        }
+    }
+
+    /// Name the addresses that are jumped to, called or loaded into `I`.
+    fn name_labels(&mut self) {
+        let again: BTreeSet<_> = self.loops.values().copied().collect();
+        let mut labels = BTreeMap::from([(PROG_START, "main".to_string())]);
+
+        for (&addr, item) in &self.items {
+            let Item::Inst(inst) = *item else {
+                continue;
+            };
+            let target = (inst & 0xFFF) as usize;
+
+            if !self.items.contains_key(&target) {
+                continue;
+            }
+
+            let name = match opcode_class(inst) {
+                "2NNN" => format!("sub_{target:03X}"),
+                "1NNN" if !again.contains(&addr) => format!("label_{target:03X}"),
+                "BNNN" => format!("table_{target:03X}"),
+                "ANNN" if matches!(self.items[&target], Item::Inst(_)) => {
+                    format!("label_{target:03X}")
+                }
+                "ANNN" => format!("data_{target:03X}"),
+                _ => continue,
+            };
+
+            labels.entry(target).or_insert(name);
+        }
+
+        self.labels = labels;
+    }
+
+    /// Get the name of an address: its label, or the address itself.
+    fn name(&self, addr: usize) -> String {
+        self.labels
+            .get(&addr)
+            .cloned()
+            .unwrap_or_else(|| format!("{addr:#05X}"))
+    }
+
+    /// Write an instruction as an Octo statement.
+    fn statement(&self, addr: usize, inst: u16) -> String {
+        let x = (inst >> 8) & 0xF;
+        let y = (inst >> 4) & 0xF;
+        let n = inst & 0xF;
+        let nn = inst & 0xFF;
+        let nnn = (inst & 0xFFF) as usize;
+
+        match opcode_class(inst) {
+            "00E0" => "clear".to_string(),
+            "00EE" => "return".to_string(),
+            "00FE" => "lores".to_string(),
+            "00FF" => "hires".to_string(),
+            "1NNN" if self.loops.values().any(|&end| end == addr) => "again".to_string(),
+            "1NNN" => format!("jump {}", self.name(nnn)),
+            "2NNN" if self.labels.contains_key(&nnn) => self.name(nnn),
+            "2NNN" => format!(":call {nnn:#05X}"),
+            // Octo's `if ... then` skips the next statement when the condition is false.
+            "3XNN" => format!("if v{x:x} != {nn:#04X} then"),
+            "4XNN" => format!("if v{x:x} == {nn:#04X} then"),
+            // Octo only assembles these with a low nibble of 0, so others are kept as bytes.
+            "5XY0" if n == 0 => format!("if v{x:x} != v{y:x} then"),
+            "9XY0" if n == 0 => format!("if v{x:x} == v{y:x} then"),
+            "EX9E" => format!("if v{x:x} -key then"),
+            "EXA1" => format!("if v{x:x} key then"),
+            "6XNN" => format!("v{x:x} := {nn:#04X}"),
+            "7XNN" => format!("v{x:x} += {nn:#04X}"),
+            "8XY0" => format!("v{x:x} := v{y:x}"),
+            "8XY1" => format!("v{x:x} |= v{y:x}"),
+            "8XY2" => format!("v{x:x} &= v{y:x}"),
+            "8XY3" => format!("v{x:x} ^= v{y:x}"),
+            "8XY4" => format!("v{x:x} += v{y:x}"),
+            "8XY5" => format!("v{x:x} -= v{y:x}"),
+            "8XY6" => format!("v{x:x} >>= v{y:x}"),
+            "8XY7" => format!("v{x:x} =- v{y:x}"),
+            "8XYE" => format!("v{x:x} <<= v{y:x}"),
+            "ANNN" => format!("i := {}", self.name(nnn)),
+            "BNNN" => format!("jump0 {}", self.name(nnn)),
+            "CXNN" => format!("v{x:x} := random {nn:#04X}"),
+            "DXYN" => format!("sprite v{x:x} v{y:x} {n}"),
+            "FX07" => format!("v{x:x} := delay"),
+            "FX0A" => format!("v{x:x} := key"),
+            "FX15" => format!("delay := v{x:x}"),
+            "FX18" => format!("buzzer := v{x:x}"),
+            "FX1E" => format!("i += v{x:x}"),
+            "FX29" => format!("i := hex v{x:x}"),
+            "FX33" => format!("bcd v{x:x}"),
+            "FX55" => format!("save v{x:x}"),
+            "FX65" => format!("load v{x:x}"),
+            _ => format!("{:#04X} {:#04X}", inst >> 8, inst & 0xFF),
+        }
+    }
+
+    /// Check whether an address is part of the data drawn as sprites.
+    fn is_sprite(&self, addr: usize) -> bool {
+        self.analysis
+            .data
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(_, &end)| addr < end)
+    }
+
+    /// Write the Octo source.
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut depth = 0;
+        let mut pending_if = false;
+        let mut bytes = Vec::new();
+
+        let flush_bytes = |out: &mut dyn Write, bytes: &mut Vec<u8>, depth: usize| {
+            for line in bytes.chunks(BYTES_PER_LINE) {
+                let line: Vec<_> = line.iter().map(|byte| format!("{byte:#04X}")).collect();
+                writeln!(
+                    out,
+                    "{:indent$}{}",
+                    "",
+                    line.join(" "),
+                    indent = depth * 2 + 2
+                )?;
+            }
+
+            bytes.clear();
+            io::Result::Ok(())
+        };
+
+        for (&addr, item) in &self.items {
+            let label = self.labels.get(&addr);
+            let loop_start = self.loops.contains_key(&addr);
+            let sprite = matches!(item, Item::Byte(_)) && self.is_sprite(addr);
+
+            if label.is_some() || loop_start || sprite || matches!(item, Item::Inst(_)) {
+                flush_bytes(out, &mut bytes, depth)?;
+            }
+
+            if let Some(label) = label {
+                if pending_if {
+                    writeln!(out)?;
+                    pending_if = false;
+                }
+
+                writeln!(out, "\n: {label}")?;
+            }
+
+            if loop_start {
+                if pending_if {
+                    writeln!(out)?;
+                    pending_if = false;
+                }
+
+                writeln!(out, "{:indent$}loop", "", indent = depth * 2 + 2)?;
+                depth += 1;
+            }
+
+            match *item {
+                Item::Inst(inst) => {
+                    let statement = self.statement(addr, inst);
+
+                    if statement == "again" {
+                        depth = depth.saturating_sub(1);
+                    }
+
+                    if pending_if {
+                        write!(out, " {statement}")?;
+                    } else {
+                        write!(out, "{:indent$}{statement}", "", indent = depth * 2 + 2)?;
+                    }
+
+                    pending_if = statement.starts_with("if ") && !pending_if;
+
+                    if !pending_if {
+                        writeln!(out)?;
+                    }
+                }
+                Item::Byte(byte) if sprite => {
+                    if pending_if {
+                        writeln!(out)?;
+                        pending_if = false;
+                    }
+
+                    let bitmap: String = (0..8)
+                        .map(|bit| if byte << bit & 0x80 != 0 { '#' } else { '.' })
+                        .collect();
+                    writeln!(
+                        out,
+                        "{:indent$}0b{byte:08b}  # {bitmap}",
+                        "",
+                        indent = depth * 2 + 2
+                    )?;
+                }
+                Item::Byte(byte) => {
+                    if pending_if {
+                        writeln!(out)?;
+                        pending_if = false;
+                    }
+
+                    bytes.push(byte);
+                }
+            }
+        }
+
+        if pending_if {
+            writeln!(out)?;
+        }
+
+        flush_bytes(out, &mut bytes, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::read_program;
+    use std::{env, fs};
+
+    /// A program with a forward jump, unreached bytes, a loop, a call and a sprite.
+    const PROGRAM: &[u8] = &[
+        0x12, 0x04, 0x00, 0xE0, 0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x06, 0x22, 0x10, 0x12,
+        0x0E, 0xA2, 0x16, 0xD0, 0x15, 0x00, 0xEE, 0xF0, 0x90, 0x90, 0x90, 0xF0, 0x12,
+    ];
+
+    /// A program with each kind of skip.
+    const SKIPS: &[u8] = &[
+        0x30, 0x05, 0x60, 0x01, 0x41, 0x06, 0x00, 0xE0, 0x51, 0x20, 0x70, 0x01, 0x91, 0x20, 0x70,
+        0x02, 0xE0, 0x9E, 0x60, 0x03, 0xE1, 0xA1, 0x60, 0x04, 0x51, 0x21, 0x60, 0x05, 0x12, 0x18,
+    ];
+
+    fn decompile(prog: &[u8]) -> String {
+        let analysis = Analysis::new(prog);
+        let mut out = Vec::new();
+        Decompiler::new(&analysis)
+            .write(&mut out)
+            .expect("writing to a vector succeeds");
+
+        String::from_utf8(out).expect("source is UTF-8")
+    }
+
+    #[test]
+    fn labels_loops_and_sprites() {
+        assert_eq!(
+            decompile(PROGRAM),
+            "
+: main
+  jump label_204
+  0x00 0xE0
+
+: label_204
+  v0 := 0x00
+  loop
+    v0 += 0x01
+    if v0 != 0x0A then again
+  sub_210
+  loop
+  again
+
+: sub_210
+  i := data_216
+  sprite v0 v1 5
+  return
+
+: data_216
+  0b11110000  # ####....
+  0b10010000  # #..#....
+  0b10010000  # #..#....
+  0b10010000  # #..#....
+  0b11110000  # ####....
+  0x12
+"
+        );
+    }
+
+    #[test]
+    fn skips_are_inverted() {
+        assert_eq!(
+            decompile(SKIPS),
+            "
+: main
+  if v0 != 0x05 then v0 := 0x01
+  if v1 == 0x06 then clear
+  if v1 != v2 then v0 += 0x01
+  if v1 == v2 then v0 += 0x02
+  if v0 -key then v0 := 0x03
+  if v1 key then v0 := 0x04
+  loop
+    0x51 0x21
+    v0 := 0x05
+  again
+"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the octo assembler on the PATH"]
+    fn assembles_to_the_same_bytes() {
+        for (name, prog) in [("program", PROGRAM), ("skips", SKIPS)] {
+            let path =
+                env::temp_dir().join(format!("c8-decompile-{}-{name}.8o", std::process::id()));
+            fs::write(&path, decompile(prog)).expect("source can be written");
+            let result = read_program(&path);
+            let _ = fs::remove_file(&path);
+
+            assert_eq!(result.expect("source assembles"), prog, "{name}");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+/// Disassemble an instruction into the mnemonics of Cowgod's CHIP-8 technical reference, with the
+/// SUPER-CHIP resolution instructions as `LOW` and `HIGH`.
+///
+/// Words that are not instructions are shown as `???`.
+pub fn disassemble(inst: u16) -> String {
+    let x = (inst >> 8) & 0xF;
+    let y = (inst >> 4) & 0xF;
+    let n = inst & 0xF;
+    let nn = inst & 0xFF;
+    let nnn = inst & 0xFFF;
+
+    match inst >> 12 {
+        0x0 => match nnn {
+            0x0E0 => "CLS".to_string(),
+            0x0EE => "RET".to_string(),
+            0x0FE => "LOW".to_string(),
+            0x0FF => "HIGH".to_string(),
+            _ => format!("SYS {nnn:#05X}"),
+        },
+        0x1 => format!("JP {nnn:#05X}"),
+        0x2 => format!("CALL {nnn:#05X}"),
+        0x3 => format!("SE V{x:X}, {nn:#04X}"),
+        0x4 => format!("SNE V{x:X}, {nn:#04X}"),
+        0x5 => format!("SE V{x:X}, V{y:X}"),
+        0x6 => format!("LD V{x:X}, {nn:#04X}"),
+        0x7 => format!("ADD V{x:X}, {nn:#04X}"),
+        0x8 => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return "???".to_string(),
+            };
+
+            format!("{mnemonic} V{x:X}, V{y:X}")
+        }
+        0x9 => format!("SNE V{x:X}, V{y:X}"),
+        0xA => format!("LD I, {nnn:#05X}"),
+        0xB => format!("JP V0, {nnn:#05X}"),
+        0xC => format!("RND V{x:X}, {nn:#04X}"),
+        0xD => format!("DRW V{x:X}, V{y:X}, {n:#X}"),
+        0xE if nn == 0x9E => format!("SKP V{x:X}"),
+        0xE if nn == 0xA1 => format!("SKNP V{x:X}"),
+        0xF => match nn {
+            0x07 => format!("LD V{x:X}, DT"),
+            0x0A => format!("LD V{x:X}, K"),
+            0x15 => format!("LD DT, V{x:X}"),
+            0x18 => format!("LD ST, V{x:X}"),
+            0x1E => format!("ADD I, V{x:X}"),
+            0x29 => format!("LD F, V{x:X}"),
+            0x33 => format!("LD B, V{x:X}"),
+            0x55 => format!("LD [I], V{x:X}"),
+            0x65 => format!("LD V{x:X}, [I]"),
+            _ => "???".to_string(),
+        },
+        _ => "???".to_string(),
+    }
+}
+
+/// Get the class of an instruction in the conventional notation, e.g. `8XY4` or `DXYN`.
+///
+/// Instructions are classed as they are decoded, so `5XY1` is a `5XY0` like it is executed. Words
+/// that are not instructions are classed as `????`.
+pub fn opcode_class(inst: u16) -> &'static str {
+    match (inst >> 12, inst & 0xFFF, inst & 0xF, inst & 0xFF) {
+        (0x0, 0x0E0, ..) => "00E0",
+        (0x0, 0x0EE, ..) => "00EE",
+        (0x0, 0x0FE, ..) => "00FE",
+        (0x0, 0x0FF, ..) => "00FF",
+        (0x0, ..) => "0NNN",
+        (0x1, ..) => "1NNN",
+        (0x2, ..) => "2NNN",
+        (0x3, ..) => "3XNN",
+        (0x4, ..) => "4XNN",
+        (0x5, ..) => "5XY0",
+        (0x6, ..) => "6XNN",
+        (0x7, ..) => "7XNN",
+        (0x8, _, 0x0, _) => "8XY0",
+        (0x8, _, 0x1, _) => "8XY1",
+        (0x8, _, 0x2, _) => "8XY2",
+        (0x8, _, 0x3, _) => "8XY3",
+        (0x8, _, 0x4, _) => "8XY4",
+        (0x8, _, 0x5, _) => "8XY5",
+        (0x8, _, 0x6, _) => "8XY6",
+        (0x8, _, 0x7, _) => "8XY7",
+        (0x8, _, 0xE, _) => "8XYE",
+        (0x9, ..) => "9XY0",
+        (0xA, ..) => "ANNN",
+        (0xB, ..) => "BNNN",
+        (0xC, ..) => "CXNN",
+        (0xD, ..) => "DXYN",
+        (0xE, _, _, 0x9E) => "EX9E",
+        (0xE, _, _, 0xA1) => "EXA1",
+        (0xF, _, _, 0x07) => "FX07",
+        (0xF, _, _, 0x0A) => "FX0A",
+        (0xF, _, _, 0x15) => "FX15",
+        (0xF, _, _, 0x18) => "FX18",
+        (0xF, _, _, 0x1E) => "FX1E",
+        (0xF, _, _, 0x29) => "FX29",
+        (0xF, _, _, 0x33) => "FX33",
+        (0xF, _, _, 0x55) => "FX55",
+        (0xF, _, _, 0x65) => "FX65",
+        _ => "????",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode, Op};
+
+    #[test]
+    fn classes_follow_the_decoder() {
+        for inst in 0..=u16::MAX {
+            // Machine code routines are classed as 0NNN though they can't be executed.
+            let invalid = matches!(decode(inst), Op::Invalid(_)) && inst >> 12 != 0;
+            assert_eq!(opcode_class(inst) == "????", invalid, "{inst:04X}");
+        }
+
+        assert_eq!(opcode_class(0x5121), "5XY0");
+        assert_eq!(opcode_class(0x912F), "9XY0");
+        assert_eq!(disassemble(0x5121), "SE V1, V2");
+        assert_eq!(disassemble(0x912F), "SNE V1, V2");
+    }
+}
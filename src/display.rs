@@ -1,32 +1,242 @@
-use sdl2::{pixels::PixelFormatEnum, render::WindowCanvas};
+use crate::{
+    filters::{Filter, Image, FILTER_PRESETS, FILTER_SCALE},
+    osd::Osd,
+    palette::{Palette, PRESETS},
+};
+use sdl2::{
+    pixels::{Color, PixelFormatEnum},
+    rect::Rect,
+    render::{Texture, WindowCanvas},
+    video::FullscreenType,
+};
+use serde::Deserialize;
+use std::ops::Range;
 
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
 pub const SCALE: usize = 10;
-const BG_COLOR: u8 = u8::MIN; // black background
-const FG_COLOR: u8 = u8::MAX; // white foreground
+
+/// The dimensions of the SUPER-CHIP high resolution mode.
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// The number of bytes per pixel of the RGB888 texture.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The brightness below which a fading pixel is considered off.
+const FADE_THRESHOLD: f32 = 1.0 / 256.0;
+
+/// How pixels that were turned off linger on screen, to reduce the flicker of sprites that are
+/// erased and redrawn every frame. Only the rendered output is affected.
+///
+/// Frames are the 60 Hz frames of the emulated machine, so pixels linger for the same time however
+/// many frames are presented, e.g. when frames are skipped or fast-forwarded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Persistence {
+    /// Show the framebuffer as is.
+    #[default]
+    Off,
+
+    /// Show pixels that are set in either of the last two frames.
+    Or,
+
+    /// Fade pixels out, multiplying their brightness by `factor` every frame.
+    Decay { factor: f32 },
+}
+
+/// Mix two `0xRRGGBB` colours, taking `amount` of the second.
+#[allow(clippy::cast_sign_loss)]
+fn blend(from: u32, to: u32, amount: f32) -> u32 {
+    [16, 8, 0].iter().fold(0, |color, shift| {
+        let from = f32::from((from >> shift) as u8);
+        let to = f32::from((to >> shift) as u8);
+        let mixed = (to - from).mul_add(amount, from).round().clamp(0.0, 255.0) as u32;
+
+        color | mixed << shift
+    })
+}
 
 pub struct Display {
-    canvas: WindowCanvas,
-    pixels: [bool; (WIDTH * SCALE) * (HEIGHT * SCALE)],
+    /// The canvas rendered onto, or `None` when running headless.
+    canvas: Option<WindowCanvas>,
+    pixels: Vec<bool>,
+
+    /// The streaming texture the pixels are rendered into, kept between frames.
+    texture: Option<Texture>,
+
+    /// Whether the whole texture has to be redrawn on the next render.
+    redraw: bool,
+
+    /// The current width of the display in pixels.
+    width: usize,
+
+    /// The current height of the display in pixels.
+    height: usize,
+
+    /// How turned off pixels linger on screen.
+    persistence: Persistence,
+
+    /// The pixels as of the end of the last frame.
+    prev_pixels: Vec<bool>,
+
+    /// The brightness pixels keep from earlier frames when they are off.
+    afterglow: Vec<f32>,
+
+    /// The rendered brightness of each pixel.
+    brightness: Vec<f32>,
+
+    /// The palettes that can be cycled through, starting with the configured one.
+    palettes: Vec<Palette>,
+
+    /// The index of the palette in use.
+    palette: usize,
+
+    /// The filter chains that can be cycled through, starting with the configured one.
+    filter_chains: Vec<Vec<Filter>>,
+
+    /// The index of the filter chain in use.
+    filter_chain: usize,
+
+    /// The overlay drawn over the display.
+    osd: Osd,
 }
 
 impl Display {
-    pub fn new(canvas: WindowCanvas) -> Self {
+    pub fn new(
+        canvas: WindowCanvas,
+        palette: Palette,
+        persistence: Persistence,
+        filters: Vec<Filter>,
+        hud: bool,
+    ) -> Self {
+        let mut palettes = vec![palette];
+        palettes.extend(PRESETS.iter().filter(|&&preset| preset != palette));
+
+        let mut filter_chains = vec![filters];
+        for &preset in FILTER_PRESETS {
+            if preset != filter_chains[0] {
+                filter_chains.push(preset.to_vec());
+            }
+        }
+
+        Self {
+            canvas: Some(canvas),
+            pixels: vec![false; WIDTH * HEIGHT],
+            texture: None,
+            redraw: true,
+            width: WIDTH,
+            height: HEIGHT,
+            persistence,
+            prev_pixels: vec![false; WIDTH * HEIGHT],
+            afterglow: vec![0.0; WIDTH * HEIGHT],
+            brightness: vec![0.0; WIDTH * HEIGHT],
+            palettes,
+            palette: 0,
+            filter_chains,
+            filter_chain: 0,
+            osd: Osd::new(hud),
+        }
+    }
+
+    /// Create a display that is never rendered, for running headless.
+    pub fn headless() -> Self {
         Self {
-            canvas,
-            pixels: [false; (WIDTH * SCALE) * (HEIGHT * SCALE)],
+            canvas: None,
+            pixels: vec![false; WIDTH * HEIGHT],
+            texture: None,
+            redraw: true,
+            width: WIDTH,
+            height: HEIGHT,
+            persistence: Persistence::Off,
+            prev_pixels: vec![false; WIDTH * HEIGHT],
+            afterglow: vec![0.0; WIDTH * HEIGHT],
+            brightness: vec![0.0; WIDTH * HEIGHT],
+            palettes: vec![Palette::default()],
+            palette: 0,
+            filter_chains: FILTER_PRESETS
+                .iter()
+                .map(|preset| preset.to_vec())
+                .collect(),
+            filter_chain: 0,
+            osd: Osd::new(false),
         }
     }
 
+    /// Switch to the next palette, returning it.
+    pub fn cycle_palette(&mut self) -> &Palette {
+        self.palette = (self.palette + 1) % self.palettes.len();
+        self.redraw = true;
+        &self.palettes[self.palette]
+    }
+
+    /// Switch to the next chain of filters, returning it.
+    pub fn cycle_filters(&mut self) -> &[Filter] {
+        self.filter_chain = (self.filter_chain + 1) % self.filter_chains.len();
+        self.redraw = true;
+        &self.filter_chains[self.filter_chain]
+    }
+
+    /// Toggle between windowed and fullscreen mode.
+    pub fn toggle_fullscreen(&mut self) {
+        let Some(canvas) = &mut self.canvas else {
+            return;
+        };
+
+        let window = canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+
+        window
+            .set_fullscreen(fullscreen)
+            .expect("unable to toggle fullscreen");
+    }
+
+    /// Get the overlay drawn over the display.
+    pub fn osd(&mut self) -> &mut Osd {
+        &mut self.osd
+    }
+
+    /// Redraw the whole display on the next render, e.g. after the window was resized or exposed.
+    pub fn invalidate(&mut self) {
+        self.redraw = true;
+    }
+
+    /// Get the current width of the display in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the current height of the display in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Switch between the 64x32 and the SUPER-CHIP 128x64 resolution, clearing the display.
+    pub fn set_hires(&mut self, hires: bool) {
+        (self.width, self.height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        };
+
+        let len = self.width * self.height;
+        self.pixels = vec![false; len];
+        self.prev_pixels = vec![false; len];
+        self.afterglow = vec![0.0; len];
+        self.brightness = vec![0.0; len];
+    }
+
     /// Get the value of the pixel at the given coordinates.
     pub fn get_pixel(&self, x: usize, y: usize) -> bool {
-        self.pixels[x + WIDTH * y]
+        self.pixels[x + self.width * y]
     }
 
     /// Toggle the pixel at the given coordinates.
     pub fn toggle_pixel(&mut self, x: usize, y: usize) {
-        self.pixels[x + WIDTH * y] ^= true;
+        self.pixels[x + self.width * y] ^= true;
     }
 
     /// Clear the display.
@@ -34,27 +244,287 @@ impl Display {
         self.pixels.iter_mut().for_each(|p| *p = false);
     }
 
-    /// Render pixels onto the display.
-    pub fn render(&mut self) {
-        let texture_creator = self.canvas.texture_creator();
-        let mut texture = texture_creator
-            .create_texture_static(
-                PixelFormatEnum::RGB332,
-                (WIDTH * SCALE) as u32,
-                (HEIGHT * SCALE) as u32,
-            )
-            .expect("unable to create texture");
+    /// End an emulated frame, letting the pixels turned off so far linger for the next one.
+    pub fn end_frame(&mut self) {
+        match self.persistence {
+            Persistence::Off => {}
+            Persistence::Or => {
+                for ((glow, &prev), &p) in self
+                    .afterglow
+                    .iter_mut()
+                    .zip(&self.prev_pixels)
+                    .zip(&self.pixels)
+                {
+                    *glow = f32::from(u8::from(prev && !p));
+                }
+            }
+            Persistence::Decay { factor } => {
+                for (glow, &prev) in self.afterglow.iter_mut().zip(&self.prev_pixels) {
+                    let faded = if prev { 1.0 } else { *glow } * factor.clamp(0.0, 1.0);
+                    *glow = if faded < FADE_THRESHOLD { 0.0 } else { faded };
+                }
+            }
+        }
+
+        self.prev_pixels.clone_from(&self.pixels);
+    }
+
+    /// Get the rendered brightness of a pixel, from 0 for off to 1 for on.
+    fn pixel_brightness(&self, idx: usize) -> f32 {
+        if self.pixels[idx] {
+            1.0
+        } else {
+            self.afterglow[idx]
+        }
+    }
+
+    /// Update the rendered brightness of each pixel, returning the range of rows that changed.
+    fn update_brightness(&mut self) -> Option<Range<usize>> {
+        let mut changed: Option<Range<usize>> = None;
+
+        for idx in 0..self.pixels.len() {
+            let brightness = self.pixel_brightness(idx);
+
+            #[allow(clippy::float_cmp)]
+            if self.brightness[idx] != brightness {
+                let row = idx / self.width;
+                changed = Some(changed.map_or(row..row + 1, |rows| rows.start..row + 1));
+            }
+
+            self.brightness[idx] = brightness;
+        }
+
+        changed
+    }
+
+    /// Colour the given rows of pixels and apply the filters to them.
+    fn image(&self, rows: Range<usize>) -> Image {
+        let filters = &self.filter_chains[self.filter_chain];
+        let [background, foreground, ..] = self.palettes[self.palette].colors;
+        let mut image = Image {
+            width: self.width,
+            height: rows.len(),
+            pixels: self.brightness[rows.start * self.width..rows.end * self.width]
+                .iter()
+                .map(|&brightness| blend(background, foreground, brightness))
+                .collect(),
+        };
+
+        if !filters.is_empty() {
+            image = image.upscale(FILTER_SCALE);
+            image.apply(filters);
+        }
+
+        image
+    }
 
-        let pixel_data = self.pixels.map(|p| if p { FG_COLOR } else { BG_COLOR });
+    /// Upload the given rows of pixels to the texture.
+    fn upload_rows(&mut self, rows: Range<usize>) {
+        let scale = if self.filter_chains[self.filter_chain].is_empty() {
+            1
+        } else {
+            FILTER_SCALE
+        };
+        let image = self.image(rows.clone());
+        let texture = self.texture.as_mut().expect("texture was created");
+        let dirty = Rect::new(
+            0,
+            i32::try_from(rows.start * scale).unwrap_or_default(),
+            image.width as u32,
+            image.height as u32,
+        );
 
         texture
-            .update(None, &pixel_data, WIDTH * std::mem::size_of::<u8>())
+            .with_lock(dirty, |buffer, pitch| {
+                for (row, line) in image.pixels.chunks(image.width).enumerate() {
+                    for (col, color) in line.iter().enumerate() {
+                        let offset = row * pitch + col * BYTES_PER_PIXEL;
+                        buffer[offset..offset + BYTES_PER_PIXEL]
+                            .copy_from_slice(&color.to_ne_bytes());
+                    }
+                }
+            })
             .expect("unable to update texture");
+    }
+
+    /// Produce the image of the whole display as it would be rendered, without presenting it, e.g.
+    /// to measure the cost of rendering on a headless display.
+    pub fn rasterize(&mut self) -> Image {
+        self.update_brightness();
+        self.image(0..self.height)
+    }
+
+    /// Render pixels onto the display.
+    ///
+    /// Only the rows that changed since the last render are uploaded to the texture, and nothing
+    /// is presented at all if neither the display nor the overlay changed. A headless display is
+    /// never rendered.
+    pub fn render(&mut self) {
+        if self.canvas.is_none() {
+            return;
+        }
+
+        let changed_rows = self.update_brightness();
+
+        let filters = &self.filter_chains[self.filter_chain];
+        let scale = if filters.is_empty() { 1 } else { FILTER_SCALE };
+        let (texture_width, texture_height) =
+            ((self.width * scale) as u32, (self.height * scale) as u32);
+
+        if !self.texture.as_ref().is_some_and(|texture| {
+            let query = texture.query();
+            (query.width, query.height) == (texture_width, texture_height)
+        }) {
+            let texture = self
+                .canvas
+                .as_ref()
+                .expect("display isn't headless")
+                .create_texture_streaming(PixelFormatEnum::RGB888, texture_width, texture_height)
+                .expect("unable to create texture");
+
+            if let Some(old) = self.texture.replace(texture) {
+                // SAFETY: The canvas the texture was created with is still alive.
+                unsafe { old.destroy() };
+            }
+
+            self.redraw = true;
+        }
+
+        let rows = match changed_rows {
+            _ if self.redraw => Some(0..self.height),
+            // Filters mix neighbouring pixels, so they always have to be applied to the whole image.
+            Some(_) if !filters.is_empty() => Some(0..self.height),
+            rows => rows,
+        };
 
-        self.clear();
-        self.canvas
-            .copy(&texture, None, None)
+        if !self.osd.update() && rows.is_none() {
+            return;
+        }
+
+        if let Some(rows) = rows {
+            self.upload_rows(rows);
+            self.redraw = false;
+        }
+
+        // Scale the display by the largest integer factor that fits the window, or fit it to the
+        // window if it's too small, keeping the aspect ratio and leaving black bars around it.
+        let (out_width, out_height) = self
+            .canvas
+            .as_ref()
+            .expect("display isn't headless")
+            .output_size()
+            .expect("unable to get output size");
+        let (width, height) = (self.width as u32, self.height as u32);
+        let (dst_width, dst_height) = match (out_width / width).min(out_height / height) {
+            0 if out_width * height < out_height * width => (out_width, out_width * height / width),
+            0 => (out_height * width / height, out_height),
+            scale => (width * scale, height * scale),
+        };
+        let dst = Rect::new(
+            i32::try_from((out_width - dst_width) / 2).unwrap_or_default(),
+            i32::try_from((out_height - dst_height) / 2).unwrap_or_default(),
+            dst_width.max(1),
+            dst_height.max(1),
+        );
+
+        let canvas = self.canvas.as_mut().expect("display isn't headless");
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+        canvas
+            .copy(
+                self.texture.as_ref().expect("texture was created"),
+                None,
+                dst,
+            )
             .expect("unable to copy texture to canvas");
-        self.canvas.present();
+        self.osd.draw(canvas, dst);
+        canvas.present();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a headless display with the given persistence and the pixel at (0, 0) turned on at
+    /// the end of a frame.
+    fn display(persistence: Persistence) -> Display {
+        let mut display = Display::headless();
+        display.persistence = persistence;
+        display.toggle_pixel(0, 0);
+        display.end_frame();
+        display
+    }
+
+    /// The brightness of the pixel at (0, 0) over the following frames, with the pixel turned off
+    /// in the first.
+    fn fade(display: &mut Display, frames: usize) -> Vec<f32> {
+        display.toggle_pixel(0, 0);
+
+        (0..frames)
+            .map(|_| {
+                display.end_frame();
+                display.pixel_brightness(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn off_shows_the_framebuffer() {
+        let mut display = display(Persistence::Off);
+
+        assert!((display.pixel_brightness(0) - 1.0).abs() < f32::EPSILON);
+        assert_eq!(fade(&mut display, 2), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn or_shows_pixels_of_the_last_two_frames() {
+        let mut display = display(Persistence::Or);
+
+        assert_eq!(fade(&mut display, 3), [1.0, 0.0, 0.0]);
+
+        // A pixel set again before the end of the frame is simply on.
+        display.toggle_pixel(0, 0);
+        display.end_frame();
+        assert_eq!(fade(&mut display, 1), [1.0]);
+        display.toggle_pixel(0, 0);
+        display.toggle_pixel(0, 0);
+        assert!((display.pixel_brightness(0) - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn decay_fades_by_the_factor_every_frame() {
+        let mut display = display(Persistence::Decay { factor: 0.5 });
+
+        // Faded below the threshold of 1/256, a pixel is off.
+        assert_eq!(
+            fade(&mut display, 9),
+            [
+                0.5,
+                0.25,
+                0.125,
+                0.0625,
+                0.031_25,
+                0.015_625,
+                0.007_812_5,
+                0.003_906_25,
+                0.0
+            ]
+        );
+    }
+
+    #[test]
+    fn rendering_doesnt_advance_the_fade() {
+        let mut display = display(Persistence::Decay { factor: 0.5 });
+        display.toggle_pixel(0, 0);
+        display.end_frame();
+
+        // However often a frame is presented, e.g. when fast-forwarding or skipping frames.
+        for _ in 0..3 {
+            display.rasterize();
+            assert!((display.brightness[0] - 0.5).abs() < f32::EPSILON);
+        }
+
+        assert_eq!(display.update_brightness(), None);
     }
 }
@@ -0,0 +1,161 @@
+use crate::{
+    cpu::MEM_SIZE,
+    decode::{decode, Op},
+};
+use std::{fmt, rc::Rc};
+
+/// The maximum number of instructions in a basic block, so that long runs of straight-line code
+/// or data decoded as code don't take long to decode.
+const MAX_BLOCK_LEN: usize = 64;
+
+/// How instructions are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Fetch and decode every instruction as it is executed.
+    #[default]
+    Interpreter,
+
+    /// Decode basic blocks once and execute them from a cache.
+    Cached,
+
+    /// Compile basic blocks to x86-64 machine code.
+    #[cfg(target_arch = "x86_64")]
+    Jit,
+}
+
+impl Engine {
+    /// Parse an engine from a command line argument.
+    pub fn try_from_arg(arg: &str) -> Result<Self, String> {
+        match arg {
+            "interpreter" => Ok(Self::Interpreter),
+            "cached" => Ok(Self::Cached),
+            #[cfg(target_arch = "x86_64")]
+            "jit" => Ok(Self::Jit),
+            #[cfg(not(target_arch = "x86_64"))]
+            "jit" => Err("the JIT engine is only available on x86-64".to_string()),
+            _ => Err(format!("unknown engine: {arg:?}")),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interpreter => write!(f, "interpreter"),
+            Self::Cached => write!(f, "cached"),
+            #[cfg(target_arch = "x86_64")]
+            Self::Jit => write!(f, "jit"),
+        }
+    }
+}
+
+/// A run of instructions that are executed one after the other, ending with the first one after
+/// which execution may continue elsewhere.
+pub struct Block {
+    /// The decoded instructions.
+    pub ops: Vec<Op>,
+}
+
+/// Decoded basic blocks, by the address they start at.
+///
+/// Blocks are invalidated when the memory they were decoded from is written to, so that
+/// self-modifying code is executed as written.
+pub struct BlockCache {
+    /// The block starting at each address, if decoded.
+    blocks: Vec<Option<Rc<Block>>>,
+
+    /// Whether each byte of memory was decoded into a cached block.
+    decoded: Vec<bool>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![None; MEM_SIZE],
+            decoded: vec![false; MEM_SIZE],
+        }
+    }
+
+    /// Get the block starting at an address, decoding it from memory if it isn't cached.
+    pub fn block(&mut self, mem: &[u8], addr: usize) -> Rc<Block> {
+        if let Some(Some(block)) = self.blocks.get(addr) {
+            return Rc::clone(block);
+        }
+
+        let mut ops = Vec::new();
+        let mut pc = addr;
+
+        // An instruction straddling the end of memory is left to the interpreter.
+        while pc + 1 < mem.len() && ops.len() < MAX_BLOCK_LEN {
+            let inst = u16::from(mem[pc]) << 8 | u16::from(mem[pc + 1]);
+            let op = decode(inst);
+
+            ops.push(op);
+            self.decoded[pc..pc + 2].fill(true);
+            pc += 2;
+
+            if op.ends_block() {
+                break;
+            }
+        }
+
+        let block = Rc::new(Block { ops });
+        if let Some(slot) = self.blocks.get_mut(addr) {
+            *slot = Some(Rc::clone(&block));
+        }
+
+        block
+    }
+
+    /// Note a write to memory, dropping every block if the address was decoded.
+    pub fn write(&mut self, addr: usize) {
+        if self.decoded[addr] {
+            self.clear();
+        }
+    }
+
+    /// Drop every block, e.g. after the program was reloaded.
+    pub fn clear(&mut self) {
+        self.blocks.fill(None);
+        self.decoded.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Memory with the given code at 0x200.
+    fn memory(code: &[u8]) -> Vec<u8> {
+        let mut mem = vec![0; MEM_SIZE];
+        mem[0x200..0x200 + code.len()].copy_from_slice(code);
+        mem
+    }
+
+    #[test]
+    fn blocks_end_after_branches_and_writes() {
+        let mut cache = BlockCache::new();
+        let mem = memory(&[0x60, 0x01, 0xF0, 0x55, 0x12, 0x00]);
+
+        assert_eq!(cache.block(&mem, 0x200).ops, [Op::Set(0, 1), Op::Store(0)]);
+        assert_eq!(cache.block(&mem, 0x204).ops, [Op::Jump(0x200)]);
+    }
+
+    #[test]
+    fn writes_to_decoded_memory_drop_blocks() {
+        let mut cache = BlockCache::new();
+        let mut mem = memory(&[0x60, 0x01, 0x12, 0x00]);
+        let block = cache.block(&mem, 0x200);
+
+        // Memory that wasn't decoded keeps the block.
+        cache.write(0x204);
+        assert!(Rc::ptr_eq(&block, &cache.block(&mem, 0x200)));
+
+        mem[0x201] = 0x02;
+        cache.write(0x201);
+        assert_eq!(
+            cache.block(&mem, 0x200).ops,
+            [Op::Set(0, 2), Op::Jump(0x200)]
+        );
+    }
+}
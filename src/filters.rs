@@ -0,0 +1,253 @@
+// Filters work on floating point intensities derived from 8-bit colour channels.
+#![allow(clippy::cast_precision_loss, clippy::cast_sign_loss)]
+
+use serde::Deserialize;
+
+/// The size of each CHIP-8 pixel in the image the filters are applied to.
+pub const FILTER_SCALE: usize = 8;
+
+/// A post-processing filter imitating a CRT, applied in software.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    /// Darken the bottom of each row of pixels.
+    Scanlines,
+
+    /// Darken the edges of each pixel.
+    PixelGrid,
+
+    /// Let bright pixels glow onto their surroundings.
+    Bloom,
+
+    /// Bulge the image outwards like the glass of a CRT.
+    Curvature,
+}
+
+impl Filter {
+    /// The name of the filter as written in the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Scanlines => "scanlines",
+            Self::PixelGrid => "pixel-grid",
+            Self::Bloom => "bloom",
+            Self::Curvature => "curvature",
+        }
+    }
+}
+
+/// The filter chains that can be cycled through after the configured one.
+pub const FILTER_PRESETS: &[&[Filter]] = &[
+    &[],
+    &[Filter::Scanlines],
+    &[Filter::PixelGrid],
+    &[Filter::Bloom],
+    &[Filter::Scanlines, Filter::Bloom, Filter::Curvature],
+];
+
+/// An image of `0xRRGGBB` pixels.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    /// Scale the image up by an integer factor.
+    pub fn upscale(&self, factor: usize) -> Self {
+        let width = self.width * factor;
+        let height = self.height * factor;
+        let pixels = (0..width * height)
+            .map(|idx| self.pixels[(idx % width) / factor + (idx / width) / factor * self.width])
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Apply the filters in order.
+    pub fn apply(&mut self, filters: &[Filter]) {
+        for filter in filters {
+            match filter {
+                Filter::Scanlines => self.scanlines(),
+                Filter::PixelGrid => self.pixel_grid(),
+                Filter::Bloom => self.bloom(),
+                Filter::Curvature => self.curvature(),
+            }
+        }
+    }
+
+    fn scanlines(&mut self) {
+        for (idx, pixel) in self.pixels.iter_mut().enumerate() {
+            if (idx / self.width) % FILTER_SCALE >= FILTER_SCALE * 3 / 4 {
+                *pixel = scale_color(*pixel, 0.5);
+            }
+        }
+    }
+
+    fn pixel_grid(&mut self) {
+        for (idx, pixel) in self.pixels.iter_mut().enumerate() {
+            let x = (idx % self.width) % FILTER_SCALE;
+            let y = (idx / self.width) % FILTER_SCALE;
+
+            if x == 0 || y == 0 {
+                *pixel = scale_color(*pixel, 0.7);
+            }
+        }
+    }
+
+    fn bloom(&mut self) {
+        const RADIUS: usize = FILTER_SCALE;
+        const STRENGTH: f32 = 0.6;
+
+        // Blur the image with a separable box blur, then add the blur on top of it.
+        let horizontal = box_blur(&self.pixels, self.width, self.height, self.width, 1, RADIUS);
+        let blurred = box_blur(&horizontal, self.height, self.width, 1, self.width, RADIUS);
+
+        for (pixel, glow) in self.pixels.iter_mut().zip(blurred) {
+            *pixel = add_colors(*pixel, scale_color(glow, STRENGTH));
+        }
+    }
+
+    fn curvature(&mut self) {
+        const AMOUNT: f32 = 0.08;
+
+        let (width, height) = (self.width as f32, self.height as f32);
+        let mut curved = vec![0; self.pixels.len()];
+
+        for (idx, pixel) in curved.iter_mut().enumerate() {
+            // Map to [-1, 1], push out further from the centre, and map back.
+            let u = (((idx % self.width) as f32 + 0.5) / width).mul_add(2.0, -1.0);
+            let v = (((idx / self.width) as f32 + 0.5) / height).mul_add(2.0, -1.0);
+            let src_u = u * v.mul_add(v * AMOUNT, 1.0);
+            let src_v = v * u.mul_add(u * AMOUNT, 1.0);
+
+            if src_u.abs() < 1.0 && src_v.abs() < 1.0 {
+                let src_x = (src_u.mul_add(0.5, 0.5) * width) as usize;
+                let src_y = (src_v.mul_add(0.5, 0.5) * height) as usize;
+                *pixel = self.pixels[src_x + src_y * self.width];
+            }
+        }
+
+        self.pixels = curved;
+    }
+}
+
+/// Blur a line of pixels at a time with a moving average.
+///
+/// `stride` is the distance between neighbouring pixels of a line and `line_stride` the distance
+/// between the starts of neighbouring lines.
+fn box_blur(
+    pixels: &[u32],
+    len: usize,
+    lines: usize,
+    line_stride: usize,
+    stride: usize,
+    radius: usize,
+) -> Vec<u32> {
+    let mut blurred = vec![0; pixels.len()];
+
+    for line in 0..lines {
+        let start = line * line_stride;
+        let mut sums = [0u32; 3];
+
+        let channels = |pixel: u32| [(pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF];
+
+        for pos in 0..radius.min(len) {
+            for (sum, channel) in sums.iter_mut().zip(channels(pixels[start + pos * stride])) {
+                *sum += channel;
+            }
+        }
+
+        for pos in 0..len {
+            if pos + radius < len {
+                let entering = channels(pixels[start + (pos + radius) * stride]);
+                sums.iter_mut().zip(entering).for_each(|(sum, c)| *sum += c);
+            }
+
+            if pos > radius {
+                let leaving = channels(pixels[start + (pos - radius - 1) * stride]);
+                sums.iter_mut().zip(leaving).for_each(|(sum, c)| *sum -= c);
+            }
+
+            let count = (pos + radius).min(len - 1) + 1 - pos.saturating_sub(radius);
+            let [r, g, b] = sums.map(|sum| sum / count as u32);
+            blurred[start + pos * stride] = r << 16 | g << 8 | b;
+        }
+    }
+
+    blurred
+}
+
+/// Multiply each channel of a colour by a factor.
+fn scale_color(color: u32, factor: f32) -> u32 {
+    [16, 8, 0].iter().fold(0, |scaled, shift| {
+        let channel = ((color >> shift) & 0xFF) as f32 * factor;
+        scaled | (channel.round().clamp(0.0, 255.0) as u32) << shift
+    })
+}
+
+/// Add two colours, saturating each channel.
+fn add_colors(a: u32, b: u32) -> u32 {
+    [16, 8, 0].iter().fold(0, |sum, shift| {
+        let channel = ((a >> shift) & 0xFF) + ((b >> shift) & 0xFF);
+        sum | channel.min(0xFF) << shift
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A wide image that is black except for a single white CHIP-8 pixel.
+    fn image() -> Image {
+        let mut image = Image {
+            width: 64,
+            height: 32,
+            pixels: vec![0; 64 * 32],
+        };
+        image.pixels[10 + 5 * 64] = 0xFF_FFFF;
+
+        image.upscale(FILTER_SCALE)
+    }
+
+    #[test]
+    fn every_filter_on_a_non_square_image() {
+        let filters = [
+            Filter::Scanlines,
+            Filter::PixelGrid,
+            Filter::Bloom,
+            Filter::Curvature,
+        ];
+        let chains = filters
+            .iter()
+            .map(std::slice::from_ref)
+            .chain(FILTER_PRESETS.iter().copied());
+
+        for chain in chains {
+            let mut image = image();
+            image.apply(chain);
+
+            assert_eq!((image.width, image.height), (512, 256), "{chain:?}");
+            assert_eq!(image.pixels.len(), 512 * 256, "{chain:?}");
+        }
+    }
+
+    #[test]
+    fn bloom_glows_evenly_in_both_directions() {
+        let mut image = image();
+        image.apply(&[Filter::Bloom]);
+
+        // The pixel covers (80, 40) to (87, 47), so these are as far from it on each side.
+        let at = |x: usize, y: usize| image.pixels[x + y * image.width];
+        let glow = at(90, 44);
+
+        assert_ne!(glow, 0);
+        assert_eq!(at(77, 44), glow);
+        assert_eq!(at(84, 50), glow);
+        assert_eq!(at(84, 37), glow);
+        assert_eq!(at(200, 44), 0);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{
+    cpu::MEM_SIZE,
+    decode::{decode, Op},
+    quirks::Quirks,
+};
+use memmap2::{Mmap, MmapMut};
+use std::rc::Rc;
+
+/// The maximum number of instructions compiled into a block.
+const MAX_BLOCK_LEN: usize = 64;
+
+/// The offsets of the fields of `JitState` other than the registers.
+const IDXR: u8 = 16;
+const DTR: u8 = 18;
+const STR: u8 = 19;
+
+/// The flag register.
+const VF: u8 = 0xF;
+
+/// The x86-64 byte registers used by compiled code.
+const AL: u8 = 0;
+const CL: u8 = 1;
+const DL: u8 = 2;
+
+/// The x86-64 opcodes of the arithmetic instructions on byte registers.
+const ADD: u8 = 0x00;
+const OR: u8 = 0x08;
+const AND: u8 = 0x20;
+const SUB: u8 = 0x28;
+const XOR: u8 = 0x30;
+const CMP: u8 = 0x38;
+
+/// The x86-64 condition codes of the `setcc` and `jcc` instructions.
+const CARRY: u8 = 0x2;
+const NOT_CARRY: u8 = 0x3;
+const EQUAL: u8 = 0x4;
+const NOT_EQUAL: u8 = 0x5;
+
+/// The state compiled code works on, copied from and back to the CPU around each run.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JitState {
+    pub gpvr: [u8; 16],
+    pub idxr: u16,
+    pub dtr: u8,
+    pub str: u8,
+}
+
+/// Emits x86-64 machine code working on a `JitState` pointed to by `rdi`.
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    /// The `ModRM` byte addressing `[rdi + disp8]` with the given register operand.
+    const fn state(reg: u8) -> u8 {
+        0x40 | reg << 3 | 7
+    }
+
+    /// `mov reg, byte [rdi + offset]`
+    fn load(&mut self, reg: u8, offset: u8) {
+        self.code.extend([0x8A, Self::state(reg), offset]);
+    }
+
+    /// `mov byte [rdi + offset], reg`
+    fn store(&mut self, offset: u8, reg: u8) {
+        self.code.extend([0x88, Self::state(reg), offset]);
+    }
+
+    /// `mov byte [rdi + offset], imm`
+    fn store_imm(&mut self, offset: u8, imm: u8) {
+        self.code.extend([0xC6, Self::state(0), offset, imm]);
+    }
+
+    /// `mov word [rdi + offset], imm`
+    fn store_imm16(&mut self, offset: u8, imm: u16) {
+        let [lo, hi] = imm.to_le_bytes();
+        self.code
+            .extend([0x66, 0xC7, Self::state(0), offset, lo, hi]);
+    }
+
+    /// `add byte [rdi + offset], imm`
+    fn add_imm(&mut self, offset: u8, imm: u8) {
+        self.code.extend([0x80, Self::state(0), offset, imm]);
+    }
+
+    /// `cmp byte [rdi + offset], imm`
+    fn cmp_imm(&mut self, offset: u8, imm: u8) {
+        self.code.extend([0x80, Self::state(7), offset, imm]);
+    }
+
+    /// An arithmetic instruction, e.g. `add dst, src`.
+    fn arith(&mut self, opcode: u8, dst: u8, src: u8) {
+        self.code.extend([opcode, 0xC0 | src << 3 | dst]);
+    }
+
+    /// `shr reg, 1`
+    fn shr1(&mut self, reg: u8) {
+        self.code.extend([0xD0, 0xE8 | reg]);
+    }
+
+    /// `shl reg, 1`
+    fn shl1(&mut self, reg: u8) {
+        self.code.extend([0xD0, 0xE0 | reg]);
+    }
+
+    /// `setcc reg`
+    fn set_if(&mut self, condition: u8, reg: u8) {
+        self.code.extend([0x0F, 0x90 | condition, 0xC0 | reg]);
+    }
+
+    /// `jcc` over the given number of bytes.
+    fn jump_if(&mut self, condition: u8, len: u8) {
+        self.code.extend([0x70 | condition, len]);
+    }
+
+    /// Return the given address to continue at: `mov eax, addr` followed by `ret`.
+    fn exit(&mut self, addr: usize) {
+        self.code.push(0xB8);
+        self.code.extend((addr as u32).to_le_bytes());
+        self.code.push(0xC3);
+    }
+
+    /// Return the address of the instruction after the next one if the condition holds, and of
+    /// the next one otherwise.
+    fn exit_skip_if(&mut self, condition: u8, addr: usize) {
+        // `mov` doesn't change the flags the condition is taken from.
+        self.code.push(0xB8);
+        self.code.extend((addr as u32 + 2).to_le_bytes());
+        // Negating a condition flips its lowest bit.
+        self.jump_if(condition ^ 1, 5);
+        self.code.push(0xB8);
+        self.code.extend((addr as u32 + 4).to_le_bytes());
+        self.code.push(0xC3);
+    }
+
+    /// Compile an instruction at the given address, returning whether it ends the block, or
+    /// `None` if it is left to the interpreter.
+    fn compile(&mut self, op: Op, addr: usize, quirks: Quirks) -> Option<bool> {
+        // Registers are at the start of the state, so their offsets are their indices.
+        let reg = |reg: usize| reg as u8;
+
+        match op {
+            Op::Jump(nnn) => self.exit(nnn as usize),
+            Op::SkipEq(x, nn) => {
+                self.cmp_imm(reg(x), nn);
+                self.exit_skip_if(EQUAL, addr);
+            }
+            Op::SkipNe(x, nn) => {
+                self.cmp_imm(reg(x), nn);
+                self.exit_skip_if(NOT_EQUAL, addr);
+            }
+            Op::SkipEqReg(x, y) | Op::SkipNeReg(x, y) => {
+                self.load(AL, reg(x));
+                self.load(CL, reg(y));
+                self.arith(CMP, AL, CL);
+
+                let condition = if matches!(op, Op::SkipEqReg(..)) {
+                    EQUAL
+                } else {
+                    NOT_EQUAL
+                };
+                self.exit_skip_if(condition, addr);
+            }
+            Op::Set(x, nn) => self.store_imm(reg(x), nn),
+            Op::AddImm(x, nn) => self.add_imm(reg(x), nn),
+            Op::Copy(x, y) => {
+                self.load(AL, reg(y));
+                self.store(reg(x), AL);
+            }
+            Op::Or(x, y) | Op::And(x, y) | Op::Xor(x, y) => {
+                let opcode = match op {
+                    Op::Or(..) => OR,
+                    Op::And(..) => AND,
+                    _ => XOR,
+                };
+
+                self.load(AL, reg(x));
+                self.load(CL, reg(y));
+                self.arith(opcode, AL, CL);
+                self.store(reg(x), AL);
+            }
+            // The flag is stored after the result, so it wins when VF is the destination.
+            Op::Add(x, y) | Op::Sub(x, y) | Op::SubReverse(x, y) => {
+                self.load(AL, reg(x));
+                self.load(CL, reg(y));
+
+                let (result, condition) = match op {
+                    Op::Add(..) => {
+                        self.arith(ADD, AL, CL);
+                        (AL, CARRY)
+                    }
+                    Op::Sub(..) => {
+                        self.arith(SUB, AL, CL);
+                        (AL, NOT_CARRY)
+                    }
+                    _ => {
+                        self.arith(SUB, CL, AL);
+                        (CL, NOT_CARRY)
+                    }
+                };
+
+                self.set_if(condition, DL);
+                self.store(reg(x), result);
+                self.store(VF, DL);
+            }
+            Op::ShiftRight(x, y) | Op::ShiftLeft(x, y) => {
+                self.load(AL, reg(if quirks.modern_shift { x } else { y }));
+
+                if matches!(op, Op::ShiftRight(..)) {
+                    self.shr1(AL);
+                } else {
+                    self.shl1(AL);
+                }
+
+                self.set_if(CARRY, DL);
+                self.store(reg(x), AL);
+                self.store(VF, DL);
+            }
+            Op::SetIndex(nnn) => self.store_imm16(IDXR, nnn),
+            Op::GetDelay(x) => {
+                self.load(AL, DTR);
+                self.store(reg(x), AL);
+            }
+            Op::SetDelay(x) | Op::SetSound(x) => {
+                self.load(AL, reg(x));
+                self.store(
+                    if matches!(op, Op::SetDelay(_)) {
+                        DTR
+                    } else {
+                        STR
+                    },
+                    AL,
+                );
+            }
+            _ => return None,
+        }
+
+        Some(op.ends_block())
+    }
+}
+
+/// A basic block compiled to machine code.
+pub struct CompiledBlock {
+    code: Mmap,
+
+    /// The number of instructions executed by a run of the block.
+    pub len: usize,
+}
+
+impl CompiledBlock {
+    /// Run the block, returning the address execution continues at.
+    pub fn run(&self, state: &mut JitState) -> u16 {
+        // SAFETY: The code was emitted by an `Assembler` as a function taking a pointer to the state
+        // in `rdi` and returning the next address in `eax`, touching nothing but the state.
+        let code = unsafe {
+            std::mem::transmute::<*const u8, extern "sysv64" fn(*mut JitState) -> u32>(
+                self.code.as_ptr(),
+            )
+        };
+
+        code(state) as u16
+    }
+}
+
+/// Basic blocks compiled to x86-64 machine code, by the address they start at.
+///
+/// Only instructions working on registers and timers are compiled, which leaves drawing, input,
+/// subroutines and memory accesses to the interpreter. Code the program writes to is never
+/// compiled again, so self-modifying code is interpreted.
+pub struct Jit {
+    quirks: Quirks,
+
+    /// The block starting at each address, if compiled.
+    blocks: Vec<Option<Rc<CompiledBlock>>>,
+
+    /// Whether each byte of memory was compiled into a block.
+    compiled: Vec<bool>,
+
+    /// Whether each byte of memory was written by the program.
+    modified: Vec<bool>,
+}
+
+impl Jit {
+    pub fn new(quirks: Quirks) -> Self {
+        Self {
+            quirks,
+            blocks: vec![None; MEM_SIZE],
+            compiled: vec![false; MEM_SIZE],
+            modified: vec![false; MEM_SIZE],
+        }
+    }
+
+    /// Get the block starting at an address, compiling it if it isn't compiled yet, or `None` if
+    /// the instruction there is left to the interpreter.
+    pub fn block(&mut self, mem: &[u8], addr: usize) -> Option<Rc<CompiledBlock>> {
+        match self.blocks.get(addr) {
+            Some(Some(block)) => return Some(Rc::clone(block)),
+            Some(None) => {}
+            None => return None,
+        }
+
+        let mut asm = Assembler::default();
+        let mut pc = addr;
+        let mut len = 0;
+        let mut ended = false;
+
+        while pc + 1 < MEM_SIZE
+            && len < MAX_BLOCK_LEN
+            && !self.modified[pc]
+            && !self.modified[pc + 1]
+        {
+            let op = decode(u16::from(mem[pc]) << 8 | u16::from(mem[pc + 1]));
+
+            let Some(ends_block) = asm.compile(op, pc, self.quirks) else {
+                break;
+            };
+
+            self.compiled[pc..pc + 2].fill(true);
+            len += 1;
+            pc += 2;
+
+            if ends_block {
+                ended = true;
+                break;
+            }
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        if !ended {
+            asm.exit(pc);
+        }
+
+        // If no executable memory can be had, the block is left to the interpreter.
+        let mut code = MmapMut::map_anon(asm.code.len()).ok()?;
+        code.copy_from_slice(&asm.code);
+
+        let block = Rc::new(CompiledBlock {
+            code: code.make_exec().ok()?,
+            len,
+        });
+        self.blocks[addr] = Some(Rc::clone(&block));
+
+        Some(block)
+    }
+
+    /// Note a write to memory, dropping every block if the address was compiled.
+    pub fn write(&mut self, addr: usize) {
+        self.modified[addr] = true;
+
+        if self.compiled[addr] {
+            self.blocks.fill(None);
+            self.compiled.fill(false);
+        }
+    }
+
+    /// Drop every block and forget about the writes, e.g. after the program was reloaded.
+    pub fn clear(&mut self) {
+        self.blocks.fill(None);
+        self.compiled.fill(false);
+        self.modified.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Platform;
+
+    /// Memory with the given code at 0x200.
+    fn memory(code: &[u8]) -> Vec<u8> {
+        let mut mem = vec![0; MEM_SIZE];
+        mem[0x200..0x200 + code.len()].copy_from_slice(code);
+        mem
+    }
+
+    fn jit() -> Jit {
+        Jit::new(Platform::ModernChip8.quirks())
+    }
+
+    #[test]
+    fn blocks_stop_before_uncompilable_ops() {
+        let mem = memory(&[
+            0x60, 0x03, // 0x200: LD V0, 0x03
+            0x70, 0x02, // 0x202: ADD V0, 0x02
+            0x22, 0x10, // 0x204: CALL 0x210
+        ]);
+        let mut jit = jit();
+        let block = jit.block(&mem, 0x200).expect("block compiles");
+        let mut state = JitState {
+            gpvr: [0; 16],
+            idxr: 0,
+            dtr: 0,
+            str: 0,
+        };
+
+        assert_eq!(block.len, 2);
+        assert_eq!(block.run(&mut state), 0x204);
+        assert_eq!(state.gpvr[0x0], 0x05);
+
+        // The call is left to the interpreter.
+        assert!(jit.block(&mem, 0x204).is_none());
+    }
+
+    #[test]
+    fn modified_code_is_never_compiled() {
+        let mem = memory(&[
+            0x60, 0x01, // 0x200: LD V0, 0x01
+            0x12, 0x00, // 0x202: JP 0x200
+        ]);
+        let mut jit = jit();
+        let block = jit.block(&mem, 0x200).expect("block compiles");
+
+        // Memory that wasn't compiled keeps the block.
+        jit.write(0x300);
+        let same = jit.block(&mem, 0x200).expect("block is kept");
+        assert!(Rc::ptr_eq(&block, &same));
+
+        jit.write(0x201);
+        assert!(jit.block(&mem, 0x200).is_none());
+        assert_eq!(jit.block(&mem, 0x202).expect("block compiles").len, 1);
+
+        // Until the program is loaded again.
+        jit.clear();
+        assert_eq!(jit.block(&mem, 0x200).expect("block compiles").len, 2);
+    }
+}
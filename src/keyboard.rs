@@ -1,61 +1,78 @@
 use sdl2::keyboard::Scancode;
 
-#[derive(Default)]
-pub struct Keyboard([bool; 16]);
+/// The keys 0-F in order over the 1234, QWER, ASDF and ZXCV rows of a QWERTY keyboard.
+pub const DEFAULT_KEYMAP: [Scancode; 16] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::R,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::F,
+    Scancode::Z,
+    Scancode::X,
+    Scancode::C,
+    Scancode::V,
+];
+
+pub struct Keyboard {
+    /// The scancode each CHIP-8 key is mapped to.
+    keymap: [Scancode; 16],
+
+    /// The state of each CHIP-8 key.
+    pressed: [bool; 16],
+}
 
 impl Keyboard {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(keymap: [Scancode; 16]) -> Self {
+        Self {
+            keymap,
+            pressed: [false; 16],
+        }
+    }
+
+    /// Get the CHIP-8 key mapped to the given scancode, if any.
+    fn key_of(&self, scancode: Scancode) -> Option<usize> {
+        self.keymap.iter().position(|&mapped| mapped == scancode)
     }
 
     /// Press a key.
     pub fn press_key(&mut self, scancode: Scancode) {
-        match scancode {
-            Scancode::Num1 => self.0[0] = true,
-            Scancode::Num2 => self.0[1] = true,
-            Scancode::Num3 => self.0[2] = true,
-            Scancode::Num4 => self.0[3] = true,
-            Scancode::Q => self.0[4] = true,
-            Scancode::W => self.0[5] = true,
-            Scancode::E => self.0[6] = true,
-            Scancode::R => self.0[7] = true,
-            Scancode::A => self.0[8] = true,
-            Scancode::S => self.0[9] = true,
-            Scancode::D => self.0[10] = true,
-            Scancode::F => self.0[11] = true,
-            Scancode::Z => self.0[12] = true,
-            Scancode::X => self.0[13] = true,
-            Scancode::C => self.0[14] = true,
-            Scancode::V => self.0[15] = true,
-            _ => (),
+        if let Some(key) = self.key_of(scancode) {
+            self.pressed[key] = true;
         }
     }
 
     /// Release a key.
     pub fn release_key(&mut self, scancode: Scancode) {
-        match scancode {
-            Scancode::Num1 => self.0[0] = false,
-            Scancode::Num2 => self.0[1] = false,
-            Scancode::Num3 => self.0[2] = false,
-            Scancode::Num4 => self.0[3] = false,
-            Scancode::Q => self.0[4] = false,
-            Scancode::W => self.0[5] = false,
-            Scancode::E => self.0[6] = false,
-            Scancode::R => self.0[7] = false,
-            Scancode::A => self.0[8] = false,
-            Scancode::S => self.0[9] = false,
-            Scancode::D => self.0[10] = false,
-            Scancode::F => self.0[11] = false,
-            Scancode::Z => self.0[12] = false,
-            Scancode::X => self.0[13] = false,
-            Scancode::C => self.0[14] = false,
-            Scancode::V => self.0[15] = false,
-            _ => (),
+        if let Some(key) = self.key_of(scancode) {
+            self.pressed[key] = false;
         }
     }
 
     /// Check if a key is pressed.
     pub fn is_key_pressed(&self, idx: usize) -> bool {
-        self.0[idx]
+        self.pressed[idx]
+    }
+
+    /// Get the keys that are currently pressed, as a bit for each key.
+    pub fn pressed_keys(&self) -> u16 {
+        self.pressed
+            .iter()
+            .rev()
+            .fold(0, |keys, &pressed| keys << 1 | u16::from(pressed))
+    }
+
+    /// Get the lowest key that is currently pressed, if any.
+    pub fn pressed_key(&self) -> Option<u8> {
+        self.pressed
+            .iter()
+            .position(|&pressed| pressed)
+            .map(|idx| idx as u8)
     }
 }
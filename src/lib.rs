@@ -0,0 +1,43 @@
+//! The emulator core and its front end, shared by the `c8` binary, tests and benchmarks.
+
+#![warn(rust_2018_idioms, clippy::pedantic, clippy::nursery)]
+// Register names (`regx`/`regy`) and masked opcode fields are idiomatic in an emulator.
+#![allow(
+    clippy::similar_names,
+    clippy::cast_possible_truncation,
+    clippy::too_many_lines,
+    clippy::missing_const_for_fn
+)]
+// The library isn't published, it only exists to be linked by the binary, tests and benchmarks.
+#![allow(
+    clippy::must_use_candidate,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::module_name_repetitions,
+    clippy::new_without_default,
+    clippy::return_self_not_must_use
+)]
+
+pub mod analyze;
+pub mod audio;
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod decode;
+pub mod decompile;
+pub mod disasm;
+pub mod display;
+pub mod engine;
+pub mod filters;
+#[cfg(target_arch = "x86_64")]
+pub mod jit;
+pub mod keyboard;
+pub mod osd;
+pub mod palette;
+pub mod profile;
+pub mod program;
+pub mod quirks;
+pub mod romdb;
+pub mod sanitize;
+pub mod speed;
+pub mod trace;
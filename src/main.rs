@@ -1,19 +1,121 @@
 #![warn(rust_2018_idioms, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::cast_possible_truncation)]
 
-mod cpu;
-mod display;
-mod keyboard;
-
-use clap::Parser;
-use cpu::Cpu;
-use display::{Display, HEIGHT, SCALE, WIDTH};
-use std::{error::Error, fs::File, io::Read, path::PathBuf};
+use c8::{
+    analyze::Analysis,
+    audio::Beeper,
+    config::{rom_hash, Config, Profile},
+    coverage::{Coverage, SymbolMap},
+    cpu::{Cpu, RunOptions},
+    decompile::Decompiler,
+    display::{Display, HEIGHT, WIDTH},
+    engine::Engine,
+    keyboard::Keyboard,
+    profile::Profiler,
+    program::{self, read_program},
+    quirks::Platform,
+    romdb::RomDb,
+    sanitize::Sanitizer,
+    speed::Speed,
+    trace::{find_divergence, Tracer},
+};
+use clap::{Args, Parser, Subcommand};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 #[derive(Parser)]
-#[command(author, about, version, propagate_version = true)]
+#[command(
+    author,
+    about,
+    version,
+    propagate_version = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// Path to the CHIP-8 program.
-    prog_path: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print what the ROM database knows about a program.
+    Info {
+        /// Path to the CHIP-8 program.
+        prog_path: PathBuf,
+
+        /// Path to a chip-8-database `programs.json` extending the embedded database.
+        #[arg(long)]
+        rom_db: Option<PathBuf>,
+    },
+
+    /// Trace the code reachable in a program and print its functions, data and basic blocks.
+    Analyze {
+        /// Path to the CHIP-8 program.
+        prog_path: PathBuf,
+
+        /// Write the control flow graph to a file in the Graphviz DOT format.
+        #[arg(long, value_name = "FILE")]
+        dot: Option<PathBuf>,
+    },
+
+    /// Turn a program back into Octo source that assembles to the same program.
+    Decompile {
+        /// Path to the CHIP-8 program.
+        prog_path: PathBuf,
+
+        /// Write the source to a file instead of the standard output.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Run a program headless at uncapped speed and report how fast it is emulated.
+    Bench {
+        /// Path to the CHIP-8 program.
+        prog_path: PathBuf,
+
+        /// Number of frames to run.
+        #[arg(long, default_value_t = 600)]
+        frames: u32,
+
+        /// Number of instructions executed per frame [default: 10].
+        #[arg(long)]
+        ipf: Option<u32>,
+
+        /// How instructions are executed: `interpreter`, `cached` or `jit`.
+        #[arg(long, default_value = "interpreter", value_parser = Engine::try_from_arg)]
+        engine: Engine,
+
+        /// The platform to emulate, e.g. `originalChip8` or `superchip`.
+        #[arg(long, value_parser = Platform::try_from_arg)]
+        platform: Option<Platform>,
+    },
+
+    /// Compare two trace logs and report the first instruction at which they diverge.
+    TraceDiff {
+        /// The first trace log.
+        a: PathBuf,
+
+        /// The second trace log.
+        b: PathBuf,
+    },
+}
+
+#[derive(Args)]
+#[allow(clippy::struct_excessive_bools)]
+struct RunArgs {
+    /// Path to the CHIP-8 program, or to an Octo source (`.8o`) to assemble with `octo`.
+    #[arg(required = true)]
+    prog_path: Option<PathBuf>,
 
     /// Step through instructions individually.
     #[arg(short, long)]
@@ -22,10 +124,121 @@ struct Cli {
     /// Prevent the display from being shown.
     #[arg(long)]
     no_display: bool,
+
+    /// Path to the configuration file [default: <config dir>/c8/c8.toml].
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Number of instructions executed per frame.
+    #[arg(long)]
+    ipf: Option<u32>,
+
+    /// Maximum number of consecutive frames not presented when falling behind [default: 0].
+    #[arg(long)]
+    frame_skip: Option<u32>,
+
+    /// Initial size of the window relative to the 64x32 display.
+    #[arg(long)]
+    scale: Option<u32>,
+
+    /// Speed relative to real time between `0.01` and `100`, e.g. `0.5`, `2`, or `uncapped`.
+    #[arg(long, default_value = "1", value_parser = Speed::try_from_arg)]
+    speed: Speed,
+
+    /// How instructions are executed: `interpreter`, `cached` to decode basic blocks once, or
+    /// `jit` to compile them to x86-64 machine code.
+    #[arg(long, default_value = "interpreter", value_parser = Engine::try_from_arg)]
+    engine: Engine,
+
+    /// Start paused.
+    #[arg(long)]
+    paused: bool,
+
+    /// Reload the program whenever it changes on disk.
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Keep a range of memory, e.g. `E00-FFF`, when the program is reset or reloaded.
+    #[arg(long, value_name = "RANGE", value_parser = program::parse_mem_range)]
+    preserve: Vec<Range<usize>>,
+
+    /// Write a log of every executed instruction and the state before it to a file.
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions within a range of addresses, e.g. `200-2FF`.
+    #[arg(long, value_name = "RANGE", value_parser = program::parse_mem_range)]
+    trace_range: Vec<Range<usize>>,
+
+    /// Count where the program spends its instructions and print a report at exit.
+    #[arg(long)]
+    profile: bool,
+
+    /// Write the coverage of the program in the LCOV format to a file, and an annotated listing
+    /// next to it.
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+
+    /// Map addresses to source lines for coverage, with lines of `<address> <file>:<line>`.
+    #[arg(long, value_name = "FILE", requires = "coverage")]
+    symbols: Option<PathBuf>,
+
+    /// Report likely bugs, such as reads of uninitialized memory or self-modifying code.
+    #[arg(long)]
+    sanitize: bool,
+
+    /// Show frame rate, instructions per second, PC and timers over the display.
+    #[arg(long)]
+    hud: bool,
+
+    /// The platform to emulate, e.g. `originalChip8` or `superchip`.
+    #[arg(long, value_parser = Platform::try_from_arg)]
+    platform: Option<Platform>,
+
+    /// Path to a chip-8-database `programs.json` extending the embedded database.
+    #[arg(long)]
+    rom_db: Option<PathBuf>,
+
+    /// Don't detect settings from the ROM database.
+    #[arg(long)]
+    no_rom_db: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Cli::parse();
+/// Load the ROM database.
+fn load_rom_db(path: Option<&Path>) -> Result<RomDb, Box<dyn Error>> {
+    path.map_or_else(|| Ok(RomDb::embedded()), RomDb::with_file)
+}
+
+/// Run a program.
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let prog_path = args.prog_path.expect("program path is required");
+    let prog = read_program(&prog_path)?;
+    let hash = rom_hash(&prog);
+
+    // Detect recommended settings from the ROM database.
+    let detected = if args.no_rom_db {
+        None
+    } else {
+        let rom_db = load_rom_db(args.rom_db.as_deref())?;
+        rom_db.lookup(&hash).map(|(program, rom)| {
+            println!("Detected {}", program.title);
+            rom.profile()
+        })
+    };
+
+    // Resolve settings from the configuration file, with command line arguments taking precedence.
+    let config = Config::load(args.config.as_deref())?;
+    let cli = Profile {
+        platform: args.platform,
+        scale: args.scale,
+        ipf: args.ipf,
+        frame_skip: args.frame_skip,
+        hud: args.hud.then_some(true),
+        ..Profile::default()
+    };
+    let settings = config
+        .profile_for(&hash, detected.as_ref(), &cli)
+        .resolve()?;
 
     // Setup needed sdl2 facilities.
     let sdl_context = sdl2::init()?;
@@ -33,28 +246,297 @@ fn main() -> Result<(), Box<dyn Error>> {
         .video()?
         .window(
             "C8: CHIP-8 Emulator",
-            (WIDTH * SCALE) as u32,
-            (HEIGHT * SCALE) as u32,
+            WIDTH as u32 * settings.scale,
+            HEIGHT as u32 * settings.scale,
         )
         .position_centered()
         .resizable()
         .build()?;
     let canvas = window.into_canvas().build()?;
-    let event_pump = sdl_context.event_pump()?;
+    let mut event_pump = sdl_context.event_pump()?;
+    let beeper = if settings.audio_enabled {
+        Beeper::new(
+            &sdl_context.audio()?,
+            settings.audio_frequency,
+            settings.audio_volume,
+        )?
+    } else {
+        Beeper::silent()
+    };
+
+    let display = Display::new(
+        canvas,
+        settings.palette,
+        settings.persistence,
+        settings.filters,
+        settings.hud,
+    );
+    let keyboard = Keyboard::new(settings.keymap);
+    let mut cpu = Cpu::new(display, keyboard, beeper, settings.quirks);
+    cpu.set_engine(args.engine);
+
+    if let Some(path) = &args.trace {
+        cpu.set_tracer(Tracer::create(path, args.trace_range)?);
+    }
+
+    if args.profile {
+        cpu.set_profiler(Profiler::new());
+    }
 
-    let display = Display::new(canvas);
-    let mut cpu = Cpu::new(display, event_pump);
+    let symbols = args.symbols.as_deref().map(SymbolMap::load).transpose()?;
 
-    // Read the program instructions into a buffer.
-    let mut rom = File::open(args.prog_path)?;
-    let mut prog = Vec::new();
-    rom.read_to_end(&mut prog)?;
+    if args.sanitize {
+        cpu.set_sanitizer(Sanitizer::new(prog.len()));
+    }
+
+    if args.coverage.is_some() {
+        cpu.set_coverage(Coverage::new(&prog));
+    }
 
     // Execute the program.
-    cpu.execute_program(&prog, args.step, args.no_display);
+    let result = cpu.execute_program(
+        &prog,
+        &mut event_pump,
+        &RunOptions {
+            ipf: settings.ipf,
+            frame_skip: settings.frame_skip,
+            speed: args.speed,
+            paused: args.paused,
+            step: args.step,
+            no_display: args.no_display,
+            watch: args.watch.then_some(prog_path),
+            preserve: args.preserve,
+        },
+    );
 
     // Dump post-execution state.
     cpu.dump_state();
 
+    if let Some(profiler) = cpu.profiler() {
+        profiler.print_report();
+    }
+
+    if let Some(sanitizer) = cpu.sanitizer() {
+        println!("\nSANITIZER FINDINGS: {}", sanitizer.findings());
+    }
+
+    if let (Some(coverage), Some(path)) = (cpu.coverage(), &args.coverage) {
+        let listing = path.with_extension("lst");
+
+        coverage.write_listing(&listing, symbols.as_ref())?;
+        coverage.write_lcov(path, symbols.as_ref(), &listing)?;
+        coverage.print_summary();
+    }
+
+    result.map_err(|fault| format!("program faulted: {fault}").into())
+}
+
+/// Print what the ROM database knows about a program.
+fn info(prog_path: &Path, rom_db: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let prog = read_program(prog_path)?;
+    let hash = rom_hash(&prog);
+    let rom_db = load_rom_db(rom_db)?;
+
+    println!("SHA-1:       {hash}");
+
+    let Some((program, rom)) = rom_db.lookup(&hash) else {
+        println!("ROM not found in the database.");
+        return Ok(());
+    };
+
+    println!("Title:       {}", program.title);
+
+    if !program.authors.is_empty() {
+        println!("Authors:     {}", program.authors.join(", "));
+    }
+
+    if let Some(release) = &program.release {
+        println!("Release:     {release}");
+    }
+
+    if let Some(description) = &program.description {
+        println!("Description: {description}");
+    }
+
+    if let Some(file) = &rom.file {
+        println!("File:        {file}");
+    }
+
+    if !rom.platforms.is_empty() {
+        println!("Platforms:   {}", rom.platforms.join(", "));
+    }
+
+    let settings = rom.profile().resolve()?;
+
+    println!("\nRECOMMENDED SETTINGS");
+    println!("--------------------");
+    println!(
+        "PLATFORM:    {}",
+        rom.platform().map_or("unsupported", Platform::id)
+    );
+    println!("IPF:         {}", settings.ipf);
+    println!("QUIRKS:");
+    println!("    modern-shift:   {}", settings.quirks.modern_shift);
+    println!("    modern-jwo:     {}", settings.quirks.modern_jwo);
+    println!("    modern-ls:      {}", settings.quirks.modern_ls);
+    println!("    key-wait-press: {}", settings.quirks.key_wait_press);
+    println!("PALETTE:     {}", settings.palette.name);
+
+    for color in settings.palette.colors {
+        println!("    #{color:06X}");
+    }
+
+    if !rom.keys.is_empty() {
+        println!("KEYS:");
+
+        let mut keys: Vec<_> = rom.keys.iter().collect();
+        keys.sort();
+
+        for (button, key) in keys {
+            println!("    {button}: {key:X}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Statically analyze a program.
+fn analyze(prog_path: &Path, dot: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let prog = read_program(prog_path)?;
+    let analysis = Analysis::new(&prog);
+
+    analysis.print_report();
+
+    if let Some(path) = dot {
+        let mut file =
+            File::create(path).map_err(|e| format!("unable to create {}: {e}", path.display()))?;
+        analysis.write_dot(&mut file)?;
+    }
+
     Ok(())
 }
+
+/// Decompile a program into Octo source.
+fn decompile(prog_path: &Path, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let prog = read_program(prog_path)?;
+    let analysis = Analysis::new(&prog);
+    let decompiler = Decompiler::new(&analysis);
+
+    if let Some(path) = output {
+        let mut file = BufWriter::new(
+            File::create(path).map_err(|e| format!("unable to create {}: {e}", path.display()))?,
+        );
+        decompiler.write(&mut file)?;
+        file.flush()?;
+    } else {
+        decompiler.write(&mut io::stdout().lock())?;
+    }
+
+    Ok(())
+}
+
+/// Measure how fast a program is emulated, without a window and as fast as possible.
+fn bench(
+    prog_path: &Path,
+    frames: u32,
+    ipf: Option<u32>,
+    engine: Engine,
+    platform: Option<Platform>,
+) -> Result<(), Box<dyn Error>> {
+    let prog = read_program(prog_path)?;
+    let settings = Profile {
+        platform,
+        ipf,
+        ..Profile::default()
+    }
+    .resolve()?;
+
+    let mut cpu = Cpu::headless(settings.quirks);
+    cpu.set_engine(engine);
+    cpu.load_program(&prog).map_err(|fault| fault.to_string())?;
+
+    let start = Instant::now();
+
+    for _ in 0..frames {
+        cpu.run_frame(settings.ipf)
+            .map_err(|fault| format!("program faulted: {fault}"))?;
+    }
+
+    let secs = start.elapsed().as_secs_f64();
+    let insts = u64::from(frames) * u64::from(settings.ipf);
+
+    println!("BENCHMARK");
+    println!("---------");
+    println!("ENGINE:       {engine}");
+    println!("IPF:          {}", settings.ipf);
+    println!("FRAMES:       {frames}");
+    println!("INSTRUCTIONS: {insts}");
+    println!("TIME:         {secs:.3} s");
+    println!(
+        "IPS:          {:.0}",
+        f64::from(frames) * f64::from(settings.ipf) / secs
+    );
+    println!("FPS:          {:.1}", f64::from(frames) / secs);
+
+    Ok(())
+}
+
+/// Compare two trace logs, returning whether they diverge.
+fn trace_diff(a: &Path, b: &Path) -> Result<bool, Box<dyn Error>> {
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .map_err(|e| format!("unable to read trace log {}: {e}", path.display()))
+    };
+    let (a_log, b_log) = (read(a)?, read(b)?);
+
+    let Some(divergence) = find_divergence(&a_log, &b_log) else {
+        println!("The traces are identical.");
+        return Ok(false);
+    };
+
+    println!("The traces diverge at line {}:", divergence.line);
+
+    if let Some(previous) = divergence.previous {
+        println!("  after:  {previous}");
+    }
+
+    for (path, line) in [a, b].iter().zip(divergence.lines) {
+        println!("  {}: {}", path.display(), line.unwrap_or("<end of trace>"));
+    }
+
+    if !divergence.fields.is_empty() {
+        println!("\nDIFFERENCES");
+        println!("-----------");
+
+        for (key, a_val, b_val) in divergence.fields {
+            println!("{key:>4}: {a_val} != {b_val}");
+        }
+    }
+
+    Ok(true)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Info { prog_path, rom_db }) => info(&prog_path, rom_db.as_deref()),
+        Some(Command::Analyze { prog_path, dot }) => analyze(&prog_path, dot.as_deref()),
+        Some(Command::Decompile { prog_path, output }) => decompile(&prog_path, output.as_deref()),
+        Some(Command::Bench {
+            prog_path,
+            frames,
+            ipf,
+            engine,
+            platform,
+        }) => bench(&prog_path, frames, ipf, engine, platform),
+        Some(Command::TraceDiff { a, b }) => {
+            if trace_diff(&a, &b)? {
+                std::process::exit(1);
+            }
+
+            Ok(())
+        }
+        None => run(cli.run),
+    }
+}
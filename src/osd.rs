@@ -0,0 +1,219 @@
+// Text is laid out in window coordinates, which are small and signed.
+#![allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+
+use sdl2::{
+    pixels::Color,
+    rect::Rect,
+    render::{BlendMode, WindowCanvas},
+};
+use std::time::{Duration, Instant};
+
+/// How long a message stays on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// The size of each pixel of the font in window pixels.
+const OSD_SCALE: i32 = 3;
+
+/// The width and height of a glyph of the font, in font pixels.
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+
+/// The space around text, in font pixels.
+const MARGIN: i32 = 1;
+
+/// Get the rows of the 3x5 glyph of a character, the leftmost column being the highest bit.
+///
+/// Letters are shown in uppercase and unknown characters as a question mark.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 2, 2],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 2],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'O' => [2, 5, 5, 5, 2],
+        'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 6, 3],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        ' ' => [0, 0, 0, 0, 0],
+        '.' => [0, 0, 0, 0, 2],
+        ',' => [0, 0, 0, 2, 4],
+        ':' => [0, 2, 0, 2, 0],
+        '-' => [0, 0, 7, 0, 0],
+        '+' => [0, 2, 7, 2, 0],
+        '=' => [0, 7, 0, 7, 0],
+        '_' => [0, 0, 0, 0, 7],
+        '/' => [1, 1, 2, 4, 4],
+        '%' => [5, 1, 2, 4, 5],
+        '(' => [1, 2, 2, 2, 1],
+        ')' => [4, 2, 2, 2, 4],
+        '[' => [3, 2, 2, 2, 3],
+        ']' => [6, 2, 2, 2, 6],
+        '!' => [2, 2, 2, 0, 2],
+        _ => [7, 1, 2, 0, 2],
+    }
+}
+
+/// An overlay of transient messages and performance statistics drawn over the display.
+///
+/// The overlay is drawn onto the window only and never affects the emulated framebuffer.
+pub struct Osd {
+    /// The message being shown and when it was shown.
+    message: Option<(String, Instant)>,
+
+    /// Whether the heads-up display of statistics is shown.
+    hud: bool,
+
+    /// The lines of the heads-up display.
+    hud_lines: Vec<String>,
+
+    /// Whether the overlay changed since it was last drawn.
+    changed: bool,
+}
+
+impl Osd {
+    pub fn new(hud: bool) -> Self {
+        Self {
+            message: None,
+            hud,
+            hud_lines: Vec::new(),
+            changed: true,
+        }
+    }
+
+    /// Show a message for a short while, replacing any message being shown.
+    pub fn show_message(&mut self, message: impl Into<String>) {
+        self.message = Some((message.into(), Instant::now()));
+        self.changed = true;
+    }
+
+    /// Toggle the heads-up display, returning whether it is now shown.
+    pub fn toggle_hud(&mut self) -> bool {
+        self.hud = !self.hud;
+        self.changed = true;
+        self.hud
+    }
+
+    /// Check whether the heads-up display is shown.
+    pub fn hud_enabled(&self) -> bool {
+        self.hud
+    }
+
+    /// Set the lines of the heads-up display.
+    pub fn set_hud(&mut self, lines: Vec<String>) {
+        if lines != self.hud_lines {
+            self.hud_lines = lines;
+            self.changed |= self.hud;
+        }
+    }
+
+    /// Remove an expired message, returning whether the overlay has to be redrawn.
+    pub fn update(&mut self) -> bool {
+        if self
+            .message
+            .as_ref()
+            .is_some_and(|(_, shown)| shown.elapsed() >= MESSAGE_DURATION)
+        {
+            self.message = None;
+            self.changed = true;
+        }
+
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Draw the overlay onto the canvas within the given area, which is that of the display.
+    pub fn draw(&self, canvas: &mut WindowCanvas, area: Rect) {
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        if self.hud {
+            draw_text(canvas, &self.hud_lines, area.x(), area.y());
+        }
+
+        if let Some((message, _)) = &self.message {
+            let height = (GLYPH_HEIGHT + MARGIN * 2) * OSD_SCALE;
+            draw_text(canvas, &[message], area.x(), area.bottom() - height);
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+/// Draw lines of text on a translucent background, with the top left corner at the given position.
+fn draw_text(canvas: &mut WindowCanvas, lines: &[impl AsRef<str>], x: i32, y: i32) {
+    let columns = lines
+        .iter()
+        .map(|line| line.as_ref().chars().count())
+        .max()
+        .unwrap_or_default();
+
+    if columns == 0 {
+        return;
+    }
+
+    let advance = GLYPH_WIDTH + 1;
+    let line_height = GLYPH_HEIGHT + 1;
+    let width = columns as i32 * advance - 1 + MARGIN * 2;
+    let height = lines.len() as i32 * line_height - 1 + MARGIN * 2;
+
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xA0));
+    canvas
+        .fill_rect(Rect::new(
+            x,
+            y,
+            (width * OSD_SCALE) as u32,
+            (height * OSD_SCALE) as u32,
+        ))
+        .expect("unable to draw OSD background");
+
+    let mut pixels = Vec::new();
+
+    for (row, line) in lines.iter().enumerate() {
+        for (col, c) in line.as_ref().chars().enumerate() {
+            let glyph_x = MARGIN + col as i32 * advance;
+            let glyph_y = MARGIN + row as i32 * line_height;
+
+            for (dy, bits) in (0..).zip(glyph(c)) {
+                for dx in 0..GLYPH_WIDTH {
+                    if (bits >> (GLYPH_WIDTH - 1 - dx)) & 1 == 1 {
+                        pixels.push(Rect::new(
+                            x + (glyph_x + dx) * OSD_SCALE,
+                            y + (glyph_y + dy) * OSD_SCALE,
+                            OSD_SCALE as u32,
+                            OSD_SCALE as u32,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    canvas.set_draw_color(Color::WHITE);
+    canvas.fill_rects(&pixels).expect("unable to draw OSD text");
+}
@@ -0,0 +1,83 @@
+use serde::Deserialize;
+
+/// A set of display colours as `0xRRGGBB` values.
+///
+/// Colours are indexed by the bit planes a pixel is set in, as in Octo: the background, the
+/// foreground, the XO-CHIP second plane, and pixels set in both planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [u32; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0]
+    }
+}
+
+/// The built-in palettes, in the order they are cycled through.
+pub const PRESETS: &[Palette] = &[
+    Palette {
+        name: "classic",
+        colors: [0x00_00_00, 0xFF_FF_FF, 0xAA_AA_AA, 0x55_55_55],
+    },
+    Palette {
+        name: "amber",
+        colors: [0x1A_0F_00, 0xFF_B0_00, 0xCC_66_00, 0xFF_E0_A0],
+    },
+    Palette {
+        name: "green",
+        colors: [0x00_1A_00, 0x33_FF_33, 0x11_99_11, 0xAA_FF_AA],
+    },
+    Palette {
+        name: "gameboy",
+        colors: [0x9B_BC_0F, 0x0F_38_0F, 0x30_62_30, 0x8B_AC_0F],
+    },
+    Palette {
+        name: "octo",
+        colors: [0x99_66_00, 0xFF_CC_00, 0xFF_66_00, 0x66_22_00],
+    },
+];
+
+/// A palette as written in a configuration file: either the name of a preset or custom colours.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteSetting {
+    Preset(String),
+    Custom(CustomPalette),
+}
+
+/// Custom palette colours. The XO-CHIP colours default to the foreground.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomPalette {
+    pub background: u32,
+    pub foreground: u32,
+    #[serde(default)]
+    pub foreground2: Option<u32>,
+    #[serde(default)]
+    pub blend: Option<u32>,
+}
+
+impl PaletteSetting {
+    /// Look up the palette.
+    pub fn resolve(&self) -> Result<Palette, String> {
+        match self {
+            Self::Preset(name) => PRESETS
+                .iter()
+                .find(|preset| preset.name.eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| format!("unknown palette: {name:?}")),
+            Self::Custom(custom) => Ok(Palette {
+                name: "custom",
+                colors: [
+                    custom.background,
+                    custom.foreground,
+                    custom.foreground2.unwrap_or(custom.foreground),
+                    custom.blend.unwrap_or(custom.foreground),
+                ],
+            }),
+        }
+    }
+}
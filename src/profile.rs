@@ -0,0 +1,273 @@
+// Percentages of instruction counts only need to be roughly right.
+#![allow(clippy::cast_precision_loss)]
+
+use crate::{
+    cpu::MEM_SIZE,
+    disasm::{disassemble, opcode_class},
+};
+use std::collections::HashMap;
+
+/// The number of hot spots listed in the report.
+const HOT_SPOTS: usize = 20;
+
+/// Execution statistics of a subroutine.
+#[derive(Clone, Copy, Debug, Default)]
+struct Subroutine {
+    calls: u64,
+
+    /// The instructions executed from the first instruction of the subroutine up to and
+    /// including its return, counting those of the subroutines it calls.
+    instructions: u64,
+}
+
+/// Counts where a program spends its instructions.
+///
+/// Time is measured in instructions executed, which is what limits a program running at a fixed
+/// number of instructions per frame.
+pub struct Profiler {
+    /// The number of executions of the instruction at each address.
+    counts: Vec<u64>,
+
+    /// The instruction last executed at each address.
+    opcodes: Vec<u16>,
+
+    /// The number of executions of each class of instruction.
+    classes: HashMap<&'static str, u64>,
+
+    /// The subroutines called, by address.
+    subroutines: HashMap<u16, Subroutine>,
+
+    /// The subroutines being executed, with the total number of instructions when they were
+    /// entered.
+    call_stack: Vec<(u16, u64)>,
+
+    /// The total number of instructions executed.
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; MEM_SIZE],
+            opcodes: vec![0; MEM_SIZE],
+            classes: HashMap::new(),
+            subroutines: HashMap::new(),
+            call_stack: Vec::new(),
+            total: 0,
+        }
+    }
+
+    /// Start over after the program was reset or reloaded, dropping what was counted so far.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Record the execution of an instruction.
+    pub fn record(&mut self, pc: u16, inst: u16) {
+        let addr = pc as usize % MEM_SIZE;
+        let class = opcode_class(inst);
+
+        self.total += 1;
+        self.counts[addr] += 1;
+        self.opcodes[addr] = inst;
+        *self.classes.entry(class).or_default() += 1;
+
+        match class {
+            "2NNN" => {
+                let target = inst & 0xFFF;
+                self.subroutines.entry(target).or_default().calls += 1;
+                self.call_stack.push((target, self.total));
+            }
+            "00EE" => {
+                if let Some((target, entered)) = self.call_stack.pop() {
+                    self.subroutines.entry(target).or_default().instructions +=
+                        self.total - entered;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The share of all executed instructions, in percent.
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.total.max(1) as f64
+    }
+
+    /// Print the hot spots, instruction classes and subroutines sorted by the instructions spent in
+    /// them, followed by a disassembly of the executed instructions annotated with their counts.
+    pub fn print_report(&self) {
+        println!("\nPROFILE");
+        println!("-------");
+        println!("INSTRUCTIONS EXECUTED: {}", self.total);
+
+        let addrs: Vec<_> = (0..MEM_SIZE)
+            .filter(|&addr| self.counts[addr] > 0)
+            .collect();
+        let mut hot_spots = addrs.clone();
+        hot_spots.sort_by_key(|&addr| std::cmp::Reverse(self.counts[addr]));
+
+        println!("\nHOT SPOTS:");
+
+        for &addr in hot_spots.iter().take(HOT_SPOTS) {
+            println!(
+                "    {addr:#05X}  {:>10}  {:6.2}%  {}",
+                self.counts[addr],
+                self.percent(self.counts[addr]),
+                disassemble(self.opcodes[addr])
+            );
+        }
+
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by_key(|&(class, &count)| (std::cmp::Reverse(count), *class));
+
+        println!("\nINSTRUCTION CLASSES:");
+
+        for (class, &count) in classes {
+            println!("    {class}  {count:>10}  {:6.2}%", self.percent(count));
+        }
+
+        // Subroutines that haven't returned yet still count up to now.
+        let mut subroutines = self.subroutines.clone();
+        for &(target, entered) in &self.call_stack {
+            subroutines.entry(target).or_default().instructions += self.total - entered;
+        }
+
+        let mut subroutines: Vec<_> = subroutines.into_iter().collect();
+        subroutines.sort_by_key(|&(addr, sub)| (std::cmp::Reverse(sub.instructions), addr));
+
+        println!("\nSUBROUTINES:");
+        println!("    ADDR       CALLS  INSTRUCTIONS    SHARE  PER CALL");
+
+        for (addr, sub) in subroutines {
+            println!(
+                "    {addr:#05X}  {:>10}  {:>12}  {:6.2}%  {:>8.1}",
+                sub.calls,
+                sub.instructions,
+                self.percent(sub.instructions),
+                sub.instructions as f64 / sub.calls.max(1) as f64
+            );
+        }
+
+        println!("\nANNOTATED DISASSEMBLY:");
+
+        let mut prev = None;
+
+        for addr in addrs {
+            if prev.is_some_and(|prev| addr > prev + 2) {
+                println!("    ...");
+            }
+
+            println!(
+                "    {addr:#05X}  {:04X}  {:<18}  {:>10}  {:6.2}%",
+                self.opcodes[addr],
+                disassemble(self.opcodes[addr]),
+                self.counts[addr],
+                self.percent(self.counts[addr])
+            );
+
+            prev = Some(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, quirks::Platform};
+
+    /// A program calling a subroutine twice, which calls another, and then looping.
+    const PROGRAM: &[u8] = &[
+        0x22, 0x06, // 0x200: CALL 0x206
+        0x22, 0x06, // 0x202: CALL 0x206
+        0x12, 0x04, // 0x204: JP 0x204
+        0x60, 0x01, // 0x206: LD V0, 0x01
+        0x22, 0x0C, // 0x208: CALL 0x20C
+        0x00, 0xEE, // 0x20A: RET
+        0x70, 0x01, // 0x20C: ADD V0, 0x01
+        0x00, 0xEE, // 0x20E: RET
+    ];
+
+    /// Run the given number of instructions of the program with a profiler.
+    fn run(instructions: u32) -> Cpu {
+        let mut cpu = Cpu::headless(Platform::ModernChip8.quirks());
+        cpu.set_profiler(Profiler::new());
+        cpu.load_program(PROGRAM).expect("program fits");
+        cpu.run_frame(instructions).expect("program doesn't fault");
+        cpu
+    }
+
+    #[test]
+    fn nested_subroutines() {
+        let cpu = run(14);
+        let profiler = cpu.profiler().expect("profiler is set");
+
+        assert_eq!(profiler.total, 14);
+        assert!(profiler.call_stack.is_empty());
+
+        // The outer subroutine counts its own 3 instructions and the 2 of the inner one per call.
+        let outer = profiler.subroutines[&0x206];
+        assert_eq!((outer.calls, outer.instructions), (2, 10));
+
+        let inner = profiler.subroutines[&0x20C];
+        assert_eq!((inner.calls, inner.instructions), (2, 4));
+        assert_eq!(profiler.subroutines.len(), 2);
+
+        assert_eq!(profiler.counts[0x200], 1);
+        assert_eq!(profiler.counts[0x204], 2);
+        assert_eq!(profiler.counts[0x20C], 2);
+        assert_eq!(profiler.opcodes[0x20C], 0x7001);
+    }
+
+    #[test]
+    fn class_totals() {
+        let cpu = run(14);
+        let profiler = cpu.profiler().expect("profiler is set");
+        let mut classes: Vec<_> = profiler
+            .classes
+            .iter()
+            .map(|(&class, &count)| (class, count))
+            .collect();
+        classes.sort_unstable();
+
+        assert_eq!(
+            classes,
+            [
+                ("00EE", 4),
+                ("1NNN", 2),
+                ("2NNN", 4),
+                ("6XNN", 2),
+                ("7XNN", 2)
+            ]
+        );
+        assert_eq!(
+            classes.iter().map(|&(_, count)| count).sum::<u64>(),
+            profiler.total
+        );
+    }
+
+    #[test]
+    fn subroutines_entered_are_on_the_call_stack() {
+        // Stopped in the inner subroutine of the first call.
+        let cpu = run(4);
+        let profiler = cpu.profiler().expect("profiler is set");
+
+        assert_eq!(profiler.call_stack, [(0x206, 1), (0x20C, 3)]);
+        assert_eq!(profiler.subroutines[&0x206].calls, 1);
+        assert_eq!(profiler.subroutines[&0x206].instructions, 0);
+    }
+
+    #[test]
+    fn reset_drops_what_was_counted() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, 0x2300);
+        profiler.record(0x300, 0x6001);
+
+        profiler.reset();
+
+        assert_eq!(profiler.total, 0);
+        assert!(profiler.counts.iter().all(|&count| count == 0));
+        assert!(profiler.classes.is_empty());
+        assert!(profiler.subroutines.is_empty());
+        assert!(profiler.call_stack.is_empty());
+    }
+}
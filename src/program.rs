@@ -0,0 +1,136 @@
+use crate::cpu::MEM_SIZE;
+use std::{
+    env,
+    error::Error,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The command used to assemble Octo sources, invoked as `octo <source> <output>`.
+const ASSEMBLER: &str = "octo";
+
+/// How often a watched program is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Read a program into a buffer, assembling it first if it is an Octo source (`.8o`).
+pub fn read_program(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    if path.extension().is_some_and(|ext| ext == "8o") {
+        return assemble(path);
+    }
+
+    fs::read(path).map_err(|e| format!("unable to read {}: {e}", path.display()).into())
+}
+
+/// Assemble an Octo source with the external assembler.
+fn assemble(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = env::temp_dir().join(format!("c8-{}.ch8", std::process::id()));
+    let result = Command::new(ASSEMBLER)
+        .arg(path)
+        .arg(&output)
+        .output()
+        .map_err(|e| format!("unable to run the Octo assembler `{ASSEMBLER}`: {e}"))?;
+
+    if !result.status.success() {
+        return Err(format!(
+            "unable to assemble {}:\n{}{}",
+            path.display(),
+            String::from_utf8_lossy(&result.stdout),
+            String::from_utf8_lossy(&result.stderr)
+        )
+        .into());
+    }
+
+    let prog = fs::read(&output)?;
+    fs::remove_file(&output)?;
+
+    Ok(prog)
+}
+
+/// Parse a range of memory given as a command line argument, e.g. `E00-FFF` (inclusive).
+pub fn parse_mem_range(arg: &str) -> Result<Range<usize>, String> {
+    let parse = |addr: &str| {
+        let addr = addr.trim();
+        let digits = addr
+            .strip_prefix("0x")
+            .or_else(|| addr.strip_prefix("0X"))
+            .unwrap_or(addr);
+
+        usize::from_str_radix(digits, 16).ok()
+    };
+
+    arg.split_once('-')
+        .and_then(|(start, end)| Some(parse(start)?..parse(end)?.checked_add(1)?))
+        .filter(|range| !range.is_empty() && range.end <= MEM_SIZE)
+        .ok_or_else(|| format!("invalid memory range: {arg:?}"))
+}
+
+/// Watches a program on disk for changes.
+pub struct Watcher {
+    path: PathBuf,
+
+    /// The modification time of the program when it was last loaded.
+    modified: Option<SystemTime>,
+
+    /// When the program was last checked for changes.
+    last_poll: Instant,
+}
+
+impl Watcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+
+        Self {
+            path,
+            modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Check whether the program changed since it was last loaded, and reload it if so.
+    pub fn poll(&mut self) -> Option<Result<Vec<u8>, Box<dyn Error>>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+
+        self.last_poll = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+
+        self.modified = modified;
+        Some(read_program(&self.path))
+    }
+}
+
+/// Get the modification time of a file, if it exists.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_memory_ranges() {
+        assert_eq!(parse_mem_range("E00-FFF"), Ok(0xE00..0x1000));
+        assert_eq!(parse_mem_range("0x200 - 0x2FF"), Ok(0x200..0x300));
+        assert_eq!(parse_mem_range("300-300"), Ok(0x300..0x301));
+
+        for arg in [
+            "300-200",
+            "E00-1000",
+            "E00",
+            "-FFF",
+            "G00-FFF",
+            "0-FFFFFFFFFFFFFFFF",
+        ] {
+            assert!(parse_mem_range(arg).is_err(), "{arg}");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Behaviours of ambiguous instructions that differ between CHIP-8 implementations.
+///
+/// The defaults are taken from the enabled cargo features, so a build configured with e.g.
+/// `modern-shift` keeps behaving that way unless told otherwise at runtime.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// Shift VX in place instead of shifting VY into VX.
+    pub modern_shift: bool,
+
+    /// Jump to XNN + VX instead of NNN + V0.
+    pub modern_jwo: bool,
+
+    /// Leave I unchanged when loading and storing registers.
+    pub modern_ls: bool,
+
+    /// Complete the get key instruction on key press instead of key release.
+    pub key_wait_press: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            modern_shift: cfg!(feature = "modern-shift"),
+            modern_jwo: cfg!(feature = "modern-jwo"),
+            modern_ls: cfg!(feature = "modern-ls"),
+            key_wait_press: cfg!(feature = "key-wait-press"),
+        }
+    }
+}
+
+/// A partial set of quirks, as written in a configuration file.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QuirkOverrides {
+    pub modern_shift: Option<bool>,
+    pub modern_jwo: Option<bool>,
+    pub modern_ls: Option<bool>,
+    pub key_wait_press: Option<bool>,
+}
+
+impl QuirkOverrides {
+    /// Build overrides from a chip-8-database quirk table, ignoring quirks that aren't emulated.
+    pub fn from_database(quirks: &HashMap<String, bool>) -> Self {
+        Self {
+            modern_shift: quirks.get("shift").copied(),
+            modern_jwo: quirks.get("jump").copied(),
+            modern_ls: quirks.get("memoryLeaveIUnchanged").copied(),
+            key_wait_press: None,
+        }
+    }
+
+    /// Replace any quirks set in `other`.
+    pub fn overlay(&mut self, other: Self) {
+        self.modern_shift = other.modern_shift.or(self.modern_shift);
+        self.modern_jwo = other.modern_jwo.or(self.modern_jwo);
+        self.modern_ls = other.modern_ls.or(self.modern_ls);
+        self.key_wait_press = other.key_wait_press.or(self.key_wait_press);
+    }
+
+    /// Apply the overrides on top of the given quirks.
+    pub fn apply(self, quirks: Quirks) -> Quirks {
+        Quirks {
+            modern_shift: self.modern_shift.unwrap_or(quirks.modern_shift),
+            modern_jwo: self.modern_jwo.unwrap_or(quirks.modern_jwo),
+            modern_ls: self.modern_ls.unwrap_or(quirks.modern_ls),
+            key_wait_press: self.key_wait_press.unwrap_or(quirks.key_wait_press),
+        }
+    }
+}
+
+/// A CHIP-8 implementation, named as in the community chip-8-database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Platform {
+    OriginalChip8,
+    HybridVip,
+    ModernChip8,
+    Chip48,
+    SuperChip1,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// Look up a platform by its chip-8-database identifier.
+    pub fn from_id(id: &str) -> Option<Self> {
+        Some(match id {
+            "originalChip8" => Self::OriginalChip8,
+            "hybridVIP" => Self::HybridVip,
+            "modernChip8" => Self::ModernChip8,
+            "chip48" => Self::Chip48,
+            "superchip1" => Self::SuperChip1,
+            "superchip" => Self::SuperChip,
+            "xochip" => Self::XoChip,
+            _ => return None,
+        })
+    }
+
+    /// The chip-8-database identifier of the platform.
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::OriginalChip8 => "originalChip8",
+            Self::HybridVip => "hybridVIP",
+            Self::ModernChip8 => "modernChip8",
+            Self::Chip48 => "chip48",
+            Self::SuperChip1 => "superchip1",
+            Self::SuperChip => "superchip",
+            Self::XoChip => "xochip",
+        }
+    }
+
+    /// The quirks of the platform.
+    pub const fn quirks(self) -> Quirks {
+        let modern = matches!(self, Self::Chip48 | Self::SuperChip1 | Self::SuperChip);
+
+        Quirks {
+            modern_shift: modern,
+            modern_jwo: modern,
+            modern_ls: matches!(self, Self::SuperChip),
+            key_wait_press: false,
+        }
+    }
+}
+
+impl Platform {
+    /// Parse a platform from a command line argument.
+    pub fn try_from_arg(id: &str) -> Result<Self, String> {
+        Self::try_from(id.to_string())
+    }
+}
+
+impl TryFrom<String> for Platform {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::from_id(&id).ok_or_else(|| format!("unknown platform: {id:?}"))
+    }
+}
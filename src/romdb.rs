@@ -0,0 +1,336 @@
+use crate::{
+    config::Profile,
+    palette::{CustomPalette, PaletteSetting},
+    quirks::{Platform, QuirkOverrides},
+};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+/// The embedded ROM database, in the format of `programs.json` from the community chip-8-database.
+///
+/// The copy in the repository only lists the ROMs in `roms/`; `scripts/update-rom-db.sh` replaces
+/// it with the full database.
+const EMBEDDED_PROGRAMS: &str = include_str!("programs.json");
+
+/// A program in the database, which may have several known ROMs.
+#[derive(Debug, Deserialize)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, Rom>,
+}
+
+/// A specific ROM of a program, keyed by its SHA-1 hash in the database.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rom {
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    #[serde(default)]
+    pub colors: Option<Colors>,
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+}
+
+/// The colours a ROM was designed for, as `#RRGGBB` strings.
+#[derive(Debug, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+impl Rom {
+    /// The first platform of the ROM that can be emulated.
+    pub fn platform(&self) -> Option<Platform> {
+        self.platforms.iter().find_map(|id| Platform::from_id(id))
+    }
+
+    /// The recommended settings for the ROM.
+    pub fn profile(&self) -> Profile {
+        let platform = self.platform();
+        let quirks = platform
+            .and_then(|platform| self.quirky_platforms.get(platform.id()))
+            .map(QuirkOverrides::from_database)
+            .unwrap_or_default();
+
+        let palette = self
+            .colors
+            .as_ref()
+            .and_then(|colors| match &colors.pixels[..] {
+                [background, foreground, rest @ ..] => {
+                    Some(PaletteSetting::Custom(CustomPalette {
+                        background: parse_color(background)?,
+                        foreground: parse_color(foreground)?,
+                        foreground2: rest.first().and_then(|color| parse_color(color)),
+                        blend: rest.get(1).and_then(|color| parse_color(color)),
+                    }))
+                }
+                _ => None,
+            });
+
+        let keymap = self
+            .keys
+            .iter()
+            .filter(|&(_, &key)| key < 16)
+            .filter_map(|(button, &key)| {
+                let name = match button.as_str() {
+                    "up" => "Up",
+                    "down" => "Down",
+                    "left" => "Left",
+                    "right" => "Right",
+                    "a" => "Space",
+                    "b" => "Return",
+                    _ => return None,
+                };
+
+                Some((format!("{key:X}"), name.to_string()))
+            })
+            .collect();
+
+        Profile {
+            platform,
+            ipf: self.tickrate,
+            palette,
+            quirks,
+            keymap,
+            ..Profile::default()
+        }
+    }
+}
+
+/// Parse a `#RRGGBB` colour.
+fn parse_color(color: &str) -> Option<u32> {
+    color
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+}
+
+/// A database of known ROMs.
+pub struct RomDb {
+    programs: Vec<Program>,
+
+    /// The index of the program of each ROM, keyed by its lowercase SHA-1 hash.
+    index: HashMap<String, usize>,
+}
+
+impl RomDb {
+    /// Create a database of the given programs, the first of which take precedence.
+    fn new(programs: Vec<Program>) -> Self {
+        let mut index = HashMap::new();
+
+        for (idx, program) in programs.iter().enumerate() {
+            for hash in program.roms.keys() {
+                index.entry(hash.to_ascii_lowercase()).or_insert(idx);
+            }
+        }
+
+        Self { programs, index }
+    }
+
+    /// Load the embedded database.
+    pub fn embedded() -> Self {
+        Self::new(
+            serde_json::from_str(EMBEDDED_PROGRAMS).expect("embedded ROM database is invalid"),
+        )
+    }
+
+    /// Load the embedded database, extended with the `programs.json` file at the given path.
+    ///
+    /// Entries in the file take precedence over the embedded ones.
+    pub fn with_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read ROM database {}: {e}", path.display()))?;
+        let mut programs: Vec<Program> = serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid ROM database {}: {e}", path.display()))?;
+
+        programs.extend(Self::embedded().programs);
+
+        Ok(Self::new(programs))
+    }
+
+    /// Find the ROM with the given SHA-1 hash.
+    pub fn lookup(&self, hash: &str) -> Option<(&Program, &Rom)> {
+        let program = &self.programs[*self.index.get(&hash.to_ascii_lowercase())?];
+
+        program
+            .roms
+            .iter()
+            .find(|(rom_hash, _)| rom_hash.eq_ignore_ascii_case(hash))
+            .map(|(_, rom)| (program, rom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keyboard::DEFAULT_KEYMAP, quirks::Quirks};
+    use sdl2::keyboard::Scancode;
+    use std::collections::BTreeMap;
+
+    /// A program in the shape of the upstream `programs.json`, including fields that aren't used.
+    const PROGRAMS: &str = r##"[
+      {
+        "title": "Example",
+        "origin": { "type": "gamejam", "reference": "Octojam 1" },
+        "description": "A game.",
+        "release": "2014",
+        "copyright": "Someone",
+        "license": "CC0",
+        "authors": ["Someone", "Someone else"],
+        "images": ["example.png"],
+        "urls": ["https://example.com"],
+        "roms": {
+          "0123456789ABCDEF0123456789ABCDEF01234567": {
+            "file": "example.ch8",
+            "embeddedTitle": "EXAMPLE",
+            "description": "The jam version.",
+            "platforms": ["megachip8", "superchip", "xochip"],
+            "quirkyPlatforms": {
+              "superchip": { "shift": false, "memoryLeaveIUnchanged": false, "wrap": true },
+              "xochip": { "jump": true }
+            },
+            "tickrate": 30,
+            "startAddress": 512,
+            "screenRotation": 0,
+            "fontStyle": "octo",
+            "touchInputMode": "none",
+            "colors": {
+              "pixels": ["#000000", "#FF8000", "#00FF00", "#ffffff"],
+              "buzzer": "#FFAA00",
+              "silence": "#000000"
+            },
+            "keys": { "up": 5, "down": 8, "a": 6, "b": 16, "player2Up": 1 }
+          }
+        }
+      },
+      {
+        "title": "Unsupported",
+        "roms": { "89abcdef0123456789abcdef0123456789abcdef": { "platforms": ["megachip8"] } }
+      }
+    ]"##;
+
+    fn db() -> RomDb {
+        RomDb::new(serde_json::from_str(PROGRAMS).expect("valid database"))
+    }
+
+    #[test]
+    fn parses_the_upstream_format() {
+        let db = db();
+        let (program, rom) = db
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .expect("ROM is found by its lowercase hash");
+
+        assert_eq!(program.title, "Example");
+        assert_eq!(program.authors, ["Someone", "Someone else"]);
+        assert_eq!(program.release.as_deref(), Some("2014"));
+        assert_eq!(rom.file.as_deref(), Some("example.ch8"));
+        assert_eq!(rom.tickrate, Some(30));
+        assert!(db
+            .lookup("0000000000000000000000000000000000000000")
+            .is_none());
+    }
+
+    #[test]
+    fn the_first_program_of_a_hash_wins() {
+        let mut programs: Vec<Program> = serde_json::from_str(PROGRAMS).expect("valid database");
+        let mut programs_again: Vec<Program> =
+            serde_json::from_str(PROGRAMS).expect("valid database");
+        programs_again[0].title = "Shadowed".to_string();
+        programs.append(&mut programs_again);
+
+        let db = RomDb::new(programs);
+        let (program, _) = db
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .expect("ROM is found");
+        assert_eq!(program.title, "Example");
+    }
+
+    #[test]
+    fn rom_profile() {
+        let db = db();
+        let (_, rom) = db
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .expect("ROM is found");
+        let profile = rom.profile();
+
+        // The first platform that can be emulated, with the quirks listed for it.
+        assert_eq!(profile.platform, Some(Platform::SuperChip));
+        assert_eq!(profile.ipf, Some(30));
+
+        let settings = profile.resolve().expect("valid settings");
+        assert_eq!(
+            settings.quirks,
+            Quirks {
+                modern_shift: false,
+                modern_ls: false,
+                ..Platform::SuperChip.quirks()
+            }
+        );
+        assert_eq!(
+            profile.palette,
+            Some(PaletteSetting::Custom(CustomPalette {
+                background: 0x00_0000,
+                foreground: 0xFF_8000,
+                foreground2: Some(0x00_FF00),
+                blend: Some(0xFF_FFFF),
+            }))
+        );
+
+        let (_, rom) = db
+            .lookup("89abcdef0123456789abcdef0123456789abcdef")
+            .expect("ROM is found");
+        assert_eq!(rom.profile().platform, None);
+    }
+
+    #[test]
+    fn key_mapping() {
+        let db = db();
+        let (_, rom) = db
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .expect("ROM is found");
+        let profile = rom.profile();
+
+        // Keys past F and buttons without a key of their own are left out.
+        assert_eq!(
+            profile.keymap,
+            BTreeMap::from([
+                ("5".to_string(), "Up".to_string()),
+                ("6".to_string(), "Space".to_string()),
+                ("8".to_string(), "Down".to_string()),
+            ])
+        );
+
+        let keymap = profile.resolve().expect("valid settings").keymap;
+        assert_eq!(keymap[0x5], Scancode::Up);
+        assert_eq!(keymap[0x6], Scancode::Space);
+        assert_eq!(keymap[0x8], Scancode::Down);
+        assert_eq!(keymap[0x0], DEFAULT_KEYMAP[0x0]);
+    }
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!(parse_color("#FF8000"), Some(0xFF_8000));
+        assert_eq!(parse_color("#ff8000"), Some(0xFF_8000));
+        assert_eq!(parse_color("FF8000"), None);
+        assert_eq!(parse_color("#F80"), None);
+        assert_eq!(parse_color("#FF800000"), None);
+        assert_eq!(parse_color("#GG8000"), None);
+    }
+
+    #[test]
+    fn embedded_database_is_valid() {
+        assert!(!RomDb::embedded().programs.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    cpu::{FONT, FONT_START, MEM_SIZE, PROG_START},
+    disasm::{disassemble, opcode_class},
+    trace::TraceState,
+};
+use std::{collections::HashSet, ops::Range};
+
+/// The stack depth from which calls are reported, as the stack only holds 16 return addresses.
+const STACK_WARN_DEPTH: usize = 14;
+
+/// Checks executed instructions for behaviour that is most likely a bug in the program.
+///
+/// Findings are reported once per address and kind, so that a bug in a loop doesn't flood the
+/// output.
+pub struct Sanitizer {
+    /// The addresses the ROM was loaded to.
+    rom: Range<usize>,
+
+    /// Whether each byte of memory was loaded or written.
+    initialized: Vec<bool>,
+
+    /// Whether each byte of memory was executed as part of an instruction.
+    executed: Vec<bool>,
+
+    /// The findings reported so far, by address and kind.
+    reported: HashSet<(u16, &'static str)>,
+}
+
+impl Sanitizer {
+    pub fn new(prog_len: usize) -> Self {
+        let mut sanitizer = Self {
+            rom: 0..0,
+            initialized: Vec::new(),
+            executed: Vec::new(),
+            reported: HashSet::new(),
+        };
+        sanitizer.reset(prog_len);
+
+        sanitizer
+    }
+
+    /// Forget everything but the findings, after a program of the given length was loaded.
+    pub fn reset(&mut self, prog_len: usize) {
+        self.rom = PROG_START..(PROG_START + prog_len).min(MEM_SIZE);
+        self.initialized = vec![false; MEM_SIZE];
+        self.executed = vec![false; MEM_SIZE];
+        self.initialized[FONT_START..FONT_START + FONT.len()].fill(true);
+        self.initialized[self.rom.clone()].fill(true);
+    }
+
+    /// The number of findings reported.
+    pub fn findings(&self) -> usize {
+        self.reported.len()
+    }
+
+    /// Report a finding about the instruction about to be executed, unless it was reported before.
+    fn report(&mut self, state: &TraceState<'_>, kind: &'static str, explanation: &str) {
+        if self.reported.insert((state.pc, kind)) {
+            eprintln!(
+                "sanitizer: PC {:#05X} {:04X} ({}): {explanation}",
+                state.pc,
+                state.inst,
+                disassemble(state.inst)
+            );
+        }
+    }
+
+    /// Check the instruction about to be executed, given whether the display is in the high
+    /// resolution mode.
+    pub fn check(&mut self, state: &TraceState<'_>, hires: bool) {
+        let pc = state.pc as usize;
+        let x = ((state.inst >> 8) & 0xF) as usize;
+        let n = (state.inst & 0xF) as usize;
+        let idxr = state.idxr as usize;
+
+        for addr in [pc, pc + 1].into_iter().filter(|&addr| addr < MEM_SIZE) {
+            if !self.rom.contains(&addr) {
+                let explanation = if self.initialized[addr] {
+                    format!("executes {addr:#05X}, which was written by the program")
+                } else {
+                    format!("executes {addr:#05X}, which was never loaded from the ROM")
+                };
+
+                self.report(state, "execute", &explanation);
+                break;
+            }
+        }
+
+        let (reads, writes) = match opcode_class(state.inst) {
+            "DXYN" if n == 0 && hires => (32, 0),
+            "DXYN" => (n, 0),
+            "FX33" => (0, 3),
+            "FX55" => (0, x + 1),
+            "FX65" => (x + 1, 0),
+            "2NNN" if state.stack.len() >= STACK_WARN_DEPTH => {
+                let depth = state.stack.len() + 1;
+                self.report(state, "stack", &format!("stack depth {depth} of 16"));
+                (0, 0)
+            }
+            _ => (0, 0),
+        };
+
+        let len = reads + writes;
+        if idxr + len > MEM_SIZE {
+            self.report(
+                state,
+                "index",
+                &format!("I ({idxr:#05X}) + {len} bytes extends past the end of memory"),
+            );
+        }
+
+        let accessed = idxr.min(MEM_SIZE)..(idxr + len).min(MEM_SIZE);
+
+        if reads > 0 {
+            if let Some(addr) = accessed.clone().find(|&addr| !self.initialized[addr]) {
+                let explanation = format!("reads uninitialized memory at {addr:#05X}");
+                self.report(state, "uninitialized", &explanation);
+            }
+        }
+
+        if writes > 0 {
+            if accessed.start < PROG_START {
+                let explanation = format!(
+                    "writes to {:#05X}, in the interpreter area below {PROG_START:#05X}",
+                    accessed.start
+                );
+                self.report(state, "interpreter", &explanation);
+            }
+
+            if let Some(addr) = accessed.clone().find(|&addr| self.executed[addr]) {
+                let explanation =
+                    format!("overwrites {addr:#05X}, which was executed (self-modifying code)");
+                self.report(state, "self-modifying", &explanation);
+            }
+
+            self.initialized[accessed].fill(true);
+        }
+
+        for addr in [pc, pc + 1].into_iter().filter(|&addr| addr < MEM_SIZE) {
+            self.executed[addr] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, quirks::Platform};
+
+    /// Run the given number of instructions of a program and get the findings, sorted by address
+    /// and kind.
+    ///
+    /// Faults end the program early, as the instructions causing them are checked before they
+    /// are executed.
+    fn findings(prog: &[u8], instructions: u32) -> Vec<(u16, &'static str)> {
+        let mut cpu = Cpu::headless(Platform::ModernChip8.quirks());
+        cpu.set_sanitizer(Sanitizer::new(prog.len()));
+        cpu.load_program(prog).expect("program fits");
+        let _ = cpu.run_frame(instructions);
+
+        let sanitizer = cpu.sanitizer().expect("sanitizer is set");
+        let mut findings: Vec<_> = sanitizer.reported.iter().copied().collect();
+        findings.sort_unstable();
+
+        assert_eq!(sanitizer.findings(), findings.len());
+        findings
+    }
+
+    #[test]
+    fn clean_program() {
+        let prog = [
+            0x60, 0x05, // 0x200: LD V0, 0x05
+            0xF0, 0x29, // 0x202: LD F, V0
+            0xD0, 0x15, // 0x204: DRW V0, V1, 0x5
+            0xA3, 0x00, // 0x206: LD I, 0x300
+            0xF0, 0x33, // 0x208: LD B, V0
+            0xF2, 0x65, // 0x20A: LD V2, [I]
+            0x22, 0x10, // 0x20C: CALL 0x210
+            0x12, 0x0E, // 0x20E: JP 0x20E
+            0x00, 0xEE, // 0x210: RET
+        ];
+
+        assert_eq!(findings(&prog, 20), []);
+    }
+
+    #[test]
+    fn uninitialized_read() {
+        let prog = [
+            0xA3, 0x00, // 0x200: LD I, 0x300
+            0xF0, 0x65, // 0x202: LD V0, [I]
+            0x12, 0x02, // 0x204: JP 0x202
+        ];
+
+        // Reported once, although the read is repeated.
+        assert_eq!(findings(&prog, 10), [(0x202, "uninitialized")]);
+    }
+
+    #[test]
+    fn deep_stack() {
+        let prog = [
+            0x22, 0x00, // 0x200: CALL 0x200
+        ];
+
+        assert_eq!(findings(&prog, 15), [(0x200, "stack")]);
+    }
+
+    #[test]
+    fn executing_data() {
+        // Code written to memory past the ROM and jumped to.
+        let written = [
+            0xA3, 0x00, // 0x200: LD I, 0x300
+            0x60, 0x13, // 0x202: LD V0, 0x13
+            0x61, 0x00, // 0x204: LD V1, 0x00
+            0xF1, 0x55, // 0x206: LD [I], V1
+            0x13, 0x00, // 0x208: JP 0x300
+        ];
+
+        assert_eq!(findings(&written, 10), [(0x300, "execute")]);
+
+        // A jump to memory that was never loaded.
+        let never_loaded = [
+            0x13, 0x00, // 0x200: JP 0x300
+        ];
+
+        assert_eq!(findings(&never_loaded, 2), [(0x300, "execute")]);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let prog = [
+            0xA2, 0x00, // 0x200: LD I, 0x200
+            0xF0, 0x55, // 0x202: LD [I], V0
+        ];
+
+        assert_eq!(findings(&prog, 2), [(0x202, "self-modifying")]);
+    }
+
+    #[test]
+    fn write_to_interpreter_area() {
+        let prog = [
+            0xA0, 0x00, // 0x200: LD I, 0x000
+            0xF0, 0x55, // 0x202: LD [I], V0
+        ];
+
+        assert_eq!(findings(&prog, 2), [(0x202, "interpreter")]);
+    }
+
+    #[test]
+    fn index_past_the_end_of_memory() {
+        let prog = [
+            0xAF, 0xFF, // 0x200: LD I, 0xFFF
+            0xF0, 0x33, // 0x202: LD B, V0
+        ];
+
+        assert_eq!(findings(&prog, 2), [(0x202, "index")]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, ops::RangeInclusive, time::Duration};
+
+/// The speeds that are stepped through with the speed hotkeys, below uncapped.
+const SPEED_STEPS: &[f64] = &[0.25, 0.5, 1.0, 2.0, 4.0];
+
+/// The range of speed factors that can be given, beyond which frame durations stop making sense.
+const FACTOR_RANGE: RangeInclusive<f64> = 0.01..=100.0;
+
+/// The speed of emulation relative to real time.
+///
+/// Speed only changes how long a frame lasts: every frame still executes the same number of
+/// instructions and ticks the timers once, so programs behave the same at any speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    /// Run frames this many times as fast as the 60 Hz timer clock.
+    Factor(f64),
+
+    /// Run frames as fast as possible.
+    Uncapped,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self::Factor(1.0)
+    }
+}
+
+impl Speed {
+    /// Parse a speed given as a command line argument: a factor or `uncapped`.
+    pub fn try_from_arg(arg: &str) -> Result<Self, String> {
+        if arg.eq_ignore_ascii_case("uncapped") {
+            return Ok(Self::Uncapped);
+        }
+
+        let factor: f64 = arg
+            .trim_end_matches('x')
+            .parse()
+            .map_err(|_| format!("invalid speed: {arg:?}"))?;
+
+        if FACTOR_RANGE.contains(&factor) {
+            Ok(Self::Factor(factor))
+        } else {
+            Err(format!(
+                "speed out of range: {arg:?} (must be between {}x and {}x, or uncapped)",
+                FACTOR_RANGE.start(),
+                FACTOR_RANGE.end()
+            ))
+        }
+    }
+
+    /// The real duration of a frame lasting `frame` at normal speed, or `None` if uncapped.
+    pub fn frame_duration(self, frame: Duration) -> Option<Duration> {
+        match self {
+            Self::Factor(factor) => Some(frame.div_f64(factor)),
+            Self::Uncapped => None,
+        }
+    }
+
+    /// Check whether frames are run faster than real time.
+    pub fn is_fast(self) -> bool {
+        match self {
+            Self::Factor(factor) => factor > 1.0,
+            Self::Uncapped => true,
+        }
+    }
+
+    /// The next step up in speed.
+    pub fn faster(self) -> Self {
+        match self {
+            Self::Factor(factor) => SPEED_STEPS
+                .iter()
+                .find(|&&step| step > factor)
+                .map_or(Self::Uncapped, |&step| Self::Factor(step)),
+            Self::Uncapped => Self::Uncapped,
+        }
+    }
+
+    /// The next step down in speed.
+    pub fn slower(self) -> Self {
+        let factor = match self {
+            Self::Factor(factor) => factor,
+            Self::Uncapped => f64::INFINITY,
+        };
+
+        SPEED_STEPS
+            .iter()
+            .rev()
+            .find(|&&step| step < factor)
+            .map_or(self, |&step| Self::Factor(step))
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Factor(factor) => write!(f, "{factor}x"),
+            Self::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_speed() {
+        assert_eq!(Speed::try_from_arg("2x"), Ok(Speed::Factor(2.0)));
+        assert_eq!(Speed::try_from_arg("0.01"), Ok(Speed::Factor(0.01)));
+        assert_eq!(Speed::try_from_arg("Uncapped"), Ok(Speed::Uncapped));
+
+        for arg in ["fast", "0", "-1", "1e-300", "100.5", "inf", "NaN"] {
+            assert!(Speed::try_from_arg(arg).is_err(), "{arg}");
+        }
+
+        // The extremes still give a valid frame duration.
+        let frame = Duration::from_millis(16);
+        assert!(Speed::Factor(0.01).frame_duration(frame).is_some());
+        assert!(Speed::Factor(100.0).frame_duration(frame).is_some());
+    }
+}
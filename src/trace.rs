@@ -0,0 +1,400 @@
+use crate::disasm::disassemble;
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::Path,
+};
+
+/// The state of the CPU before an instruction is executed.
+#[derive(Clone, Copy)]
+pub struct TraceState<'a> {
+    pub pc: u16,
+    pub inst: u16,
+    pub gpvr: &'a [u8; 16],
+    pub idxr: u16,
+    pub dtr: u8,
+    pub str: u8,
+    pub stack: &'a [u16],
+    pub mem: &'a [u8],
+
+    /// A hash of memory, written as `MEM` if given.
+    pub mem_hash: Option<u32>,
+}
+
+/// Formats the state as a line of a trace log, e.g.
+///
+/// ```text
+/// V0:00 V1:00 ... VF:00 I:0000 DT:00 ST:00 SP:01 PC:0200 PCMEM:00,E0,A2,2A MEM:9E0C4A3B ; CLS
+/// ```
+///
+/// Each field is a `KEY:VALUE` pair with a hexadecimal value: `V0` to `VF`, `I`, `DT` and `ST`,
+/// `SP` as the depth of the stack, `PC` and `PCMEM` as the 4 bytes of memory at it, `--` past
+/// the end of memory. `MEM`, a hash of all of memory, follows if the state has one, and the
+/// disassembly of the instruction comes last as a comment after `;`. Tools comparing traces
+/// compare the fields and ignore the comment.
+impl fmt::Display for TraceState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (reg, val) in self.gpvr.iter().enumerate() {
+            write!(f, "V{reg:X}:{val:02X} ")?;
+        }
+
+        write!(
+            f,
+            "I:{:04X} DT:{:02X} ST:{:02X} SP:{:02X} PC:{:04X} PCMEM:",
+            self.idxr,
+            self.dtr,
+            self.str,
+            self.stack.len(),
+            self.pc
+        )?;
+
+        for offset in 0..4 {
+            let separator = if offset == 0 { "" } else { "," };
+
+            match self.mem.get(self.pc as usize + offset) {
+                Some(byte) => write!(f, "{separator}{byte:02X}")?,
+                None => write!(f, "{separator}--")?,
+            }
+        }
+
+        if let Some(hash) = self.mem_hash {
+            write!(f, " MEM:{hash:08X}")?;
+        }
+
+        write!(f, " ; {}", disassemble(self.inst))
+    }
+}
+
+/// Hash memory with 32-bit FNV-1a.
+fn memory_hash(mem: &[u8]) -> u32 {
+    mem.iter().fold(0x811C_9DC5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Writes a trace log of executed instructions.
+pub struct Tracer {
+    out: BufWriter<File>,
+
+    /// The ranges of addresses of the instructions traced, or all if empty.
+    ranges: Vec<Range<usize>>,
+
+    /// The hash of memory, until it's written to.
+    mem_hash: Option<u32>,
+}
+
+impl Tracer {
+    /// Create a trace log at the given path, tracing only instructions within the given ranges
+    /// of addresses if any are given.
+    pub fn create(path: &Path, ranges: Vec<Range<usize>>) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)
+            .map_err(|e| format!("unable to create trace log {}: {e}", path.display()))?;
+
+        Ok(Self {
+            out: BufWriter::new(file),
+            ranges,
+            mem_hash: None,
+        })
+    }
+
+    /// Note that memory was written to, so that it's hashed again at the next instruction
+    /// traced rather than before every one.
+    pub fn memory_written(&mut self) {
+        self.mem_hash = None;
+    }
+
+    /// Write the state before an instruction to the log.
+    pub fn trace(&mut self, state: &TraceState<'_>) -> io::Result<()> {
+        let pc = state.pc as usize;
+
+        if self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)) {
+            let hash = *self.mem_hash.get_or_insert_with(|| memory_hash(state.mem));
+            let state = TraceState {
+                mem_hash: Some(hash),
+                ..*state
+            };
+
+            writeln!(self.out, "{state}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The first line at which two trace logs differ.
+pub struct Divergence<'a> {
+    /// The number of the line, starting at 1.
+    pub line: usize,
+
+    /// The differing lines, or `None` for a log that ended before.
+    pub lines: [Option<&'a str>; 2],
+
+    /// The line before, of the instruction that most likely caused the divergence.
+    pub previous: Option<&'a str>,
+
+    /// The fields with differing values.
+    pub fields: Vec<(&'a str, &'a str, &'a str)>,
+}
+
+/// Split a line of a trace log into its `KEY:VALUE` fields, ignoring the trailing comment.
+fn fields(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    line.split(';')
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|field| field.split_once(':'))
+}
+
+/// Find the first line at which two trace logs differ.
+///
+/// Only fields present in both lines are compared, so that logs of emulators that trace
+/// different parts of the state can be compared.
+pub fn find_divergence<'a>(a: &'a str, b: &'a str) -> Option<Divergence<'a>> {
+    let mut a_lines = a.lines().filter(|line| !line.trim().is_empty());
+    let mut b_lines = b.lines().filter(|line| !line.trim().is_empty());
+    let mut previous = None;
+
+    for line in 1.. {
+        let (a_line, b_line) = match (a_lines.next(), b_lines.next()) {
+            (None, None) => return None,
+            (Some(a_line), Some(b_line)) => (a_line, b_line),
+            lines => {
+                return Some(Divergence {
+                    line,
+                    lines: [lines.0, lines.1],
+                    previous,
+                    fields: Vec::new(),
+                })
+            }
+        };
+
+        let diverging: Vec<_> = fields(a_line)
+            .filter_map(|(key, a_val)| {
+                fields(b_line)
+                    .find(|&(b_key, _)| b_key.eq_ignore_ascii_case(key))
+                    .filter(|&(_, b_val)| !b_val.eq_ignore_ascii_case(a_val))
+                    .map(|(_, b_val)| (key, a_val, b_val))
+            })
+            .collect();
+
+        if !diverging.is_empty() {
+            return Some(Divergence {
+                line,
+                lines: [Some(a_line), Some(b_line)],
+                previous,
+                fields: diverging,
+            });
+        }
+
+        previous = Some(a_line);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_format() {
+        let mut gpvr = [0; 16];
+        gpvr[0xA] = 0x3C;
+        let mut mem = [0; 0x1000];
+        mem[0x200..0x204].copy_from_slice(&[0x00, 0xE0, 0xA2, 0x2A]);
+
+        let state = TraceState {
+            pc: 0x200,
+            inst: 0x00E0,
+            gpvr: &gpvr,
+            idxr: 0x22A,
+            dtr: 0x10,
+            str: 0,
+            stack: &[0x204],
+            mem: &mem,
+            mem_hash: Some(memory_hash(&mem)),
+        };
+
+        assert_eq!(
+            state.to_string(),
+            format!(
+                "V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00 VA:3C VB:00 VC:00 \
+                 VD:00 VE:00 VF:00 I:022A DT:10 ST:00 SP:01 PC:0200 PCMEM:00,E0,A2,2A MEM:{:08X} \
+                 ; CLS",
+                memory_hash(&mem)
+            )
+        );
+
+        // Memory past the end is left out.
+        let state = TraceState { pc: 0xFFE, ..state };
+        assert!(state.to_string().contains("PC:0FFE PCMEM:00,00,--,-- MEM:"));
+
+        // Without a hash, `MEM` is left out.
+        let state = TraceState {
+            mem_hash: None,
+            ..state
+        };
+        assert!(state.to_string().ends_with("PCMEM:00,00,--,-- ; CLS"));
+    }
+
+    #[test]
+    fn memory_is_hashed_again_after_writes() {
+        fn state(mem: &[u8]) -> TraceState<'_> {
+            TraceState {
+                pc: 0x200,
+                inst: 0,
+                gpvr: &[0; 16],
+                idxr: 0,
+                dtr: 0,
+                str: 0,
+                stack: &[],
+                mem,
+                mem_hash: None,
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("c8-trace-{}.log", std::process::id()));
+        let mut tracer = Tracer::create(&path, Vec::new()).expect("creates the log");
+        let mut mem = vec![0; 0x1000];
+
+        tracer.trace(&state(&mem)).expect("traces");
+        mem[0x300] = 0xFF;
+        // Until told of the write, the tracer keeps the hash it has.
+        tracer.trace(&state(&mem)).expect("traces");
+        tracer.memory_written();
+        tracer.trace(&state(&mem)).expect("traces");
+        drop(tracer);
+
+        let log = std::fs::read_to_string(&path).expect("reads the log");
+        std::fs::remove_file(&path).expect("removes the log");
+        let hashes: Vec<_> = log
+            .lines()
+            .filter_map(|line| fields(line).find(|&(key, _)| key == "MEM"))
+            .map(|(_, hash)| hash.to_string())
+            .collect();
+
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[1], hashes[2]);
+        assert_eq!(hashes[2], format!("{:08X}", memory_hash(&mem)));
+    }
+
+    /// The state of a CPU, owning what a `TraceState` borrows.
+    #[derive(Clone)]
+    struct State {
+        gpvr: [u8; 16],
+        idxr: u16,
+        dtr: u8,
+        str: u8,
+        stack: Vec<u16>,
+        pc: u16,
+        mem: Vec<u8>,
+    }
+
+    impl State {
+        fn new() -> Self {
+            let mut mem = vec![0; 0x1000];
+            mem[0x200..0x204].copy_from_slice(&[0x60, 0x01, 0xA3, 0x00]);
+
+            Self {
+                gpvr: [0; 16],
+                idxr: 0,
+                dtr: 0,
+                str: 0,
+                stack: Vec::new(),
+                pc: 0x200,
+                mem,
+            }
+        }
+
+        fn line(&self) -> String {
+            let inst =
+                u16::from_be_bytes([self.mem[self.pc as usize], self.mem[self.pc as usize + 1]]);
+
+            TraceState {
+                pc: self.pc,
+                inst,
+                gpvr: &self.gpvr,
+                idxr: self.idxr,
+                dtr: self.dtr,
+                str: self.str,
+                stack: &self.stack,
+                mem: &self.mem,
+                mem_hash: Some(memory_hash(&self.mem)),
+            }
+            .to_string()
+        }
+    }
+
+    /// The keys of the fields that differ between two logs, which diverge at their second line.
+    fn diverging_fields(change: impl FnOnce(&mut State)) -> Vec<String> {
+        let state = State::new();
+        let mut changed = state.clone();
+        change(&mut changed);
+
+        let a = format!("{}\n{}\n", state.line(), state.line());
+        let b = format!("{}\n{}\n", state.line(), changed.line());
+        let divergence = find_divergence(&a, &b).expect("the logs diverge");
+
+        assert_eq!(divergence.line, 2);
+        assert_eq!(divergence.previous, Some(state.line().as_str()));
+
+        divergence
+            .fields
+            .iter()
+            .map(|&(key, _, _)| key.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn diverging_fields_are_reported() {
+        // A change to the state, and the fields it makes differ.
+        type Case = (fn(&mut State), &'static [&'static str]);
+
+        let cases: [Case; 9] = [
+            (|state| state.gpvr[0x3] = 1, &["V3"]),
+            (|state| state.gpvr[0xF] = 1, &["VF"]),
+            (|state| state.idxr = 0x300, &["I"]),
+            (|state| state.dtr = 1, &["DT"]),
+            (|state| state.str = 1, &["ST"]),
+            (|state| state.stack.push(0x202), &["SP"]),
+            (|state| state.pc = 0x202, &["PC", "PCMEM"]),
+            (|state| state.mem[0x203] = 0x02, &["PCMEM", "MEM"]),
+            (|state| state.mem[0x300] = 0xFF, &["MEM"]),
+        ];
+
+        for (change, expected) in cases {
+            assert_eq!(diverging_fields(change), expected);
+        }
+    }
+
+    #[test]
+    fn logs_ending_early_diverge() {
+        let line = State::new().line();
+        let longer = format!("{line}\n{line}");
+        let divergence = find_divergence(&line, &longer).expect("diverges");
+
+        assert_eq!(divergence.line, 2);
+        assert_eq!(divergence.lines, [None, Some(line.as_str())]);
+        assert!(find_divergence(&line, &line).is_none());
+    }
+
+    #[test]
+    fn only_shared_fields_are_compared() {
+        // A log of another emulator, without the timers, memory hash or disassembly.
+        let line = State::new().line();
+        let other = "V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00 VA:00 VB:00 \
+                     VC:00 VD:00 VE:00 VF:00 I:0000 SP:00 PC:0200 PCMEM:60,01,A3,00";
+
+        assert!(find_divergence(&line, other).is_none());
+        assert_eq!(
+            find_divergence(&line, &other.replace("I:0000", "I:0300"))
+                .expect("diverges")
+                .fields,
+            [("I", "0000", "0300")]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Runs the test ROMs in `roms/` headless on every engine and compares the screen they end on with
+//! the expected one.
+//!
+//! Each ROM's expected framebuffer hash is listed below, and the screen itself is kept in
+//! `tests/screens/<name>.txt` to show what differs when the hash doesn't match. Running with
+//! `C8_BLESS=1` writes the screens the interpreter ends on and prints their hashes instead, which
+//! the other engines still have to match.
+
+use c8::{
+    config::rom_hash, cpu::Cpu, display::Display, engine::Engine, keyboard::DEFAULT_KEYMAP,
+    quirks::Platform,
+};
+use std::{env, fs, path::Path};
+
+/// A test ROM and the screen it is expected to end on.
+struct Case {
+    /// The name of the ROM in `roms/` and of its screen in `tests/screens/`.
+    name: &'static str,
+    platform: Platform,
+    frames: u32,
+    ipf: u32,
+
+    /// The CHIP-8 keys pressed, by the frame they are pressed at. Each is released at the next
+    /// frame, e.g. to pick an entry of a menu.
+    keys: &'static [(u32, usize)],
+
+    /// The SHA-1 hash of the framebuffer, one byte per pixel.
+    hash: &'static str,
+}
+
+/// The test ROMs.
+///
+/// Timendus's suite (corax+, flags, quirks, keypad) and the BestCoder and SCHIP tests aren't in
+/// `roms/` yet. To add one, copy it there, add its case with the platform it tests and, for the
+/// menus of the quirks and keypad tests, the keys picking an entry, and bless its screen.
+const CASES: &[Case] = &[
+    Case {
+        name: "IBM Logo",
+        platform: Platform::OriginalChip8,
+        frames: 60,
+        ipf: 15,
+        keys: &[],
+        hash: "d4598c296d5884a621d3fb2bc9461a308710fcfa",
+    },
+    Case {
+        name: "test_opcode",
+        platform: Platform::OriginalChip8,
+        frames: 60,
+        ipf: 15,
+        keys: &[],
+        hash: "d858f4e1618523ea26185fc3553b43b1ec605475",
+    },
+];
+
+/// The engines each ROM is run on, the interpreter first.
+const ENGINES: &[Engine] = &[
+    Engine::Interpreter,
+    Engine::Cached,
+    #[cfg(target_arch = "x86_64")]
+    Engine::Jit,
+];
+
+/// Draw the display as ASCII art, a `#` for each pixel that is on.
+fn screen(display: &Display) -> String {
+    (0..display.height())
+        .map(|y| {
+            let row: String = (0..display.width())
+                .map(|x| if display.get_pixel(x, y) { '#' } else { '.' })
+                .collect();
+            row + "\n"
+        })
+        .collect()
+}
+
+/// Hash the framebuffer, one byte per pixel.
+fn framebuffer_hash(display: &Display) -> String {
+    let pixels: Vec<u8> = (0..display.height())
+        .flat_map(|y| (0..display.width()).map(move |x| u8::from(display.get_pixel(x, y))))
+        .collect();
+
+    rom_hash(&pixels)
+}
+
+/// Show two screens side by side, marking the rows that differ.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut out = String::from("  expected | actual\n");
+
+    for (expected, actual) in expected.lines().zip(actual.lines()) {
+        let marker = if expected == actual { ' ' } else { '!' };
+        out += &format!("{marker} {expected} | {actual}\n");
+    }
+
+    out
+}
+
+#[test]
+fn test_roms() {
+    let bless = env::var_os("C8_BLESS").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut failures = Vec::new();
+
+    for case in CASES {
+        let prog = fs::read(root.join("roms").join(format!("{}.ch8", case.name)))
+            .expect("unable to read ROM");
+        let screen_path = root
+            .join("tests/screens")
+            .join(format!("{}.txt", case.name));
+
+        let mut expected_hash = case.hash.to_string();
+
+        for &engine in ENGINES {
+            let mut cpu = Cpu::headless(case.platform.quirks());
+            cpu.set_engine(engine);
+            cpu.load_program(&prog).expect("ROM fits into memory");
+
+            for frame in 0..case.frames {
+                for &(at, key) in case.keys {
+                    if at == frame {
+                        cpu.keyboard().press_key(DEFAULT_KEYMAP[key]);
+                    } else if at + 1 == frame {
+                        cpu.keyboard().release_key(DEFAULT_KEYMAP[key]);
+                    }
+                }
+
+                cpu.run_frame(case.ipf).expect("ROM doesn't fault");
+            }
+
+            let actual = screen(cpu.display());
+            let hash = framebuffer_hash(cpu.display());
+
+            if bless && engine == Engine::Interpreter {
+                fs::write(&screen_path, &actual).expect("unable to write screen");
+                println!("{}: {hash}", case.name);
+                expected_hash.clone_from(&hash);
+            }
+
+            if hash != expected_hash {
+                let expected = fs::read_to_string(&screen_path).unwrap_or_default();
+                failures.push(format!(
+                    "{} ended on an unexpected screen with the {engine} engine ({hash}):\n{}",
+                    case.name,
+                    diff(&expected, &actual)
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}